@@ -0,0 +1,102 @@
+/// How far apart two inputs must be before their antiderivatives are
+/// differenced. Closer inputs fall back to evaluating the curve directly, as
+/// the difference would lose too much precision.
+const TOLERANCE: f64 = 1e-4;
+
+/// Antiderivative anti-aliasing. Instead of evaluating a curve at each
+/// sample, this averages the curve over the line between consecutive
+/// samples, using its antiderivatives. This removes much of the aliasing a
+/// sharp curve would add, at the cost of a slight lowpass and a delay of half
+/// a sample per order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AntiAlias {
+    Off,
+    FirstOrder,
+    SecondOrder,
+}
+
+/// A curve which can be anti-aliased, along with its first two
+/// antiderivatives.
+pub trait Antiderivatives {
+    fn curve(&self, x: f64) -> f64;
+    fn first(&self, x: f64) -> f64;
+    /// The second antiderivative, or None if it has no closed form. Second
+    /// order anti-aliasing falls back to first order in that case.
+    fn second(&self, x: f64) -> Option<f64>;
+}
+
+/// The last two inputs to an anti-aliased curve.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct History {
+    inputs: [f64; 2],
+}
+
+impl History {
+    /// Evaluate `curve` at `x`, anti-aliased to the given order.
+    pub fn process(&mut self, order: AntiAlias, curve: &impl Antiderivatives, x: f64) -> f64 {
+        let [x1, x2] = self.inputs;
+        self.inputs = [x, x1];
+        match order {
+            AntiAlias::Off => curve.curve(x),
+            AntiAlias::FirstOrder => first_order(curve, x, x1),
+            AntiAlias::SecondOrder => match second_order(curve, x, x1, x2) {
+                Some(y) => y,
+                None => first_order(curve, x, x1),
+            },
+        }
+    }
+}
+
+fn first_order(curve: &impl Antiderivatives, x: f64, x1: f64) -> f64 {
+    let delta = x - x1;
+    if delta.abs() > TOLERANCE {
+        (curve.first(x) - curve.first(x1)) / delta
+    } else {
+        curve.curve((x + x1) / 2.0)
+    }
+}
+
+// From "Antiderivative Antialiasing for Memoryless Nonlinearities" by Bilbao,
+// Esqueda, Parker and Välimäki.
+fn second_order(curve: &impl Antiderivatives, x: f64, x1: f64, x2: f64) -> Option<f64> {
+    // The first divided difference of the second antiderivative
+    let difference = |a: f64, b: f64| {
+        if (a - b).abs() > TOLERANCE {
+            Some((curve.second(a)? - curve.second(b)?) / (a - b))
+        } else {
+            Some(curve.first((a + b) / 2.0))
+        }
+    };
+    let delta = x - x2;
+    if delta.abs() > TOLERANCE {
+        return Some(2.0 / delta * (difference(x, x1)? - difference(x1, x2)?));
+    }
+    let mean = (x + x2) / 2.0;
+    let delta = mean - x1;
+    if delta.abs() > TOLERANCE {
+        let second = (curve.second(x1)? - curve.second(mean)?) / delta;
+        Some(2.0 / delta * (curve.first(mean) + second))
+    } else {
+        Some(curve.curve((mean + x1) / 2.0))
+    }
+}
+
+impl From<f32> for AntiAlias {
+    fn from(x: f32) -> Self {
+        match (x * 3.0) as usize {
+            0 => AntiAlias::Off,
+            1 => AntiAlias::FirstOrder,
+            _ => AntiAlias::SecondOrder,
+        }
+    }
+}
+
+impl std::fmt::Display for AntiAlias {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AntiAlias::Off => write!(f, "Off"),
+            AntiAlias::FirstOrder => write!(f, "1st Order"),
+            AntiAlias::SecondOrder => write!(f, "2nd Order"),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use common::filter::Biquad;
+
+/// Splits a signal into low, mid and high bands using fourth order
+/// Linkwitz-Riley filters. The bands sum back to the input with a flat
+/// magnitude response, only shifted in phase.
+pub struct Crossover {
+    low: LinkwitzRiley,
+    // Everything above the low crossover, before it is split again.
+    rest: LinkwitzRiley,
+    mid: LinkwitzRiley,
+    high: LinkwitzRiley,
+    // The low band goes through the same phase shift as the mid and high
+    // bands do at the high crossover, so that all three line up.
+    low_allpass: [LinkwitzRiley; 2],
+}
+
+impl Crossover {
+    pub fn new() -> Crossover {
+        Crossover {
+            low: LinkwitzRiley::default(),
+            rest: LinkwitzRiley::default(),
+            mid: LinkwitzRiley::default(),
+            high: LinkwitzRiley::default(),
+            low_allpass: [LinkwitzRiley::default(); 2],
+        }
+    }
+
+    /// Set the crossover frequencies, in Hz. `low` should be below `high`.
+    /// The filter state is kept, so this may be called while processing.
+    pub fn set_frequencies(&mut self, low: f32, high: f32, sample_rate: f32) {
+        self.low.set_lowpass(low, sample_rate);
+        self.rest.set_highpass(low, sample_rate);
+        self.mid.set_lowpass(high, sample_rate);
+        self.high.set_highpass(high, sample_rate);
+        self.low_allpass[0].set_lowpass(high, sample_rate);
+        self.low_allpass[1].set_highpass(high, sample_rate);
+    }
+
+    /// Split `x` into its low, mid and high bands.
+    pub fn split(&mut self, x: f64) -> [f64; 3] {
+        let low = self.low.next(x);
+        let low = self.low_allpass[0].next(low) + self.low_allpass[1].next(low);
+        let rest = self.rest.next(x);
+        [low, self.mid.next(rest), self.high.next(rest)]
+    }
+
+    /// Clear the filter state.
+    pub fn reset(&mut self) {
+        for filter in [&mut self.low, &mut self.rest, &mut self.mid, &mut self.high] {
+            filter.reset();
+        }
+        for filter in &mut self.low_allpass {
+            filter.reset();
+        }
+    }
+}
+
+/// A fourth order Linkwitz-Riley filter, made of two Butterworth filters in
+/// series.
+#[derive(Debug, Clone, Copy, Default)]
+struct LinkwitzRiley {
+    filters: [Biquad<f64>; 2],
+}
+
+impl LinkwitzRiley {
+    fn set_lowpass(&mut self, cutoff: f32, sample_rate: f32) {
+        for filter in &mut self.filters {
+            filter.set_lowpass(cutoff, FRAC_1_SQRT_2, sample_rate);
+        }
+    }
+
+    fn set_highpass(&mut self, cutoff: f32, sample_rate: f32) {
+        for filter in &mut self.filters {
+            filter.set_highpass(cutoff, FRAC_1_SQRT_2, sample_rate);
+        }
+    }
+
+    fn next(&mut self, x: f64) -> f64 {
+        self.filters.iter_mut().fold(x, |x, filter| filter.next(x))
+    }
+
+    fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.reset();
+        }
+    }
+}
@@ -1,7 +1,15 @@
-#[macro_use]
-extern crate common;
+mod antialias;
+mod crossover;
+mod limiter;
+pub mod meter;
+mod true_peak;
 
-use std::sync::Arc;
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::f64::consts::{FRAC_2_PI, FRAC_PI_2, LN_2, TAU};
+use std::sync::{
+    atomic::{AtomicI32, AtomicUsize},
+    Arc,
+};
 
 use vst::{
     api::Supported,
@@ -11,19 +19,146 @@ use vst::{
     util::AtomicFloat,
 };
 
-use common::{ease_in_expo, make_strings};
+use common::{
+    db_to_gain,
+    delay::Delay,
+    filter::{Biquad, DcBlocker},
+    gain_to_db,
+    gesture::PendingEdits,
+    initial_delay,
+    oversample::{self, Oversampler},
+    range::{choice_label, Range},
+    rng::Rng,
+    smooth::Smoothing,
+    Automatable, VstParameters,
+};
+
+use antialias::{AntiAlias, Antiderivatives, History};
+use crossover::Crossover;
+use limiter::Limiter;
+use meter::{Loudness, Measure, Meters};
+use true_peak::TruePeak;
+
+/// The longest delay, in samples, the oversampling filters can introduce.
+const MAX_LATENCY: usize = 128;
+/// The number of factors the Oversampling parameter can select, from 1x up
+/// to `oversample::MAX_FACTOR` in powers of two.
+const OVERSAMPLE_FACTORS: usize = oversample::MAX_FACTOR.trailing_zeros() as usize + 1;
+/// The cutoff of the DC blocker, in Hz.
+const DC_BLOCKER_CUTOFF: f32 = 5.0;
+/// The corner frequency of the emphasis shelves, in Hz.
+const EMPHASIS_FREQUENCY: f32 = 2000.0;
+/// How quickly the state of the character models follows the signal, in
+/// seconds.
+const CHARACTER_TIME: f32 = 0.02;
+/// The attack and release times of the envelope the dynamic threshold
+/// follows, in seconds.
+const DYNAMICS_ATTACK: f32 = 0.03;
+const DYNAMICS_RELEASE: f32 = 0.15;
+/// How far the dynamic threshold moves at full Dynamics, in decibels.
+const DYNAMICS_RANGE: f32 = 12.0;
+/// The length of the crossfade when bypassing, in milliseconds.
+const BYPASS_FADE_MS: f32 = 10.0;
+/// The most the Match bypass mode will change the level of the dry signal
+/// by, in decibels.
+const MAX_MATCH_GAIN: f32 = 24.0;
+/// The number of points in the test tone used to measure distortion.
+const THD_POINTS: usize = 128;
+/// The highest harmonic measured.
+const THD_HARMONICS: usize = 10;
+/// The lowest bit depth the clip stage can be quantized to. The Bit Depth
+/// parameter goes from this up to 16 bits.
+const MIN_BIT_DEPTH: u32 = 4;
+/// The number of options the Bit Depth parameter has, including off.
+const BIT_DEPTHS: usize = (16 - MIN_BIT_DEPTH) as usize + 2;
+/// The bit depths the output can be dithered to.
+const OUTPUT_DITHER_DEPTHS: [u32; 3] = [16, 20, 24];
+/// How long Learn listens to the input for, in seconds.
+const LEARN_SECONDS: f32 = 2.0;
+/// How long the gains and wet/dry take to follow automation, in
+/// milliseconds.
+const SMOOTHING_MS: f32 = 20.0;
+
 struct Clipper {
     params: Arc<RawParameters>,
+    // The left and right channels, or the mid and side channels in mid/side
+    // mode.
+    channels: [Channel; 2],
+    // The dry signal, delayed to line up with the oversampled wet signal.
+    // This is kept at double precision so that the dry signal isn't
+    // truncated when the host processes in double precision.
+    delays: [Delay<f64>; 2],
+    limiter: Limiter,
+    // Dithers the final output, for each of the left and right channels.
+    output_quantizers: [Quantizer; 2],
+    // The levels the gains are worked out from, and the wet/dry, follow
+    // these so that changing them doesn't zipper.
+    smoothed: SmoothedParameters,
+    // The number of samples Learn has left to listen for, and the loudest
+    // sample it has heard, or None if it isn't listening.
+    learn: Option<(usize, f32)>,
+    // The dry signal, delayed to line up with the output of the limiter when
+    // bypassing.
+    bypass_delays: [Delay<f64>; 2],
+    // The loudness of the processed and dry signals, for Match bypassing.
+    wet_loudness: Loudness,
+    dry_loudness: Loudness,
+    // How much of the bypassed signal is in the output, from 0 to 1
+    bypass_mix: f32,
+    // How much `bypass_mix` may change per sample
+    bypass_step: f32,
+    // Shared with the editor, once there is one.
+    meters: Arc<Meters>,
+    // The parameters as of `generation`, rebuilt only when it moves. The
+    // generation starts as None so that the first block sets everything up.
+    parameters: Parameters,
+    generation: Option<usize>,
 }
 
 impl Plugin for Clipper {
     fn new(host: HostCallback) -> Self {
+        let params = RawParameters::default(host);
+        let smoothed = SmoothedParameters::new(&params, Smoothing::Linear, SMOOTHING_MS);
+        let parameters = Parameters::from(&params);
         Clipper {
-            params: Arc::new(RawParameters::default(host)),
+            params: Arc::new(params),
+            channels: [Channel::new(0), Channel::new(1)],
+            delays: [Delay::new(MAX_LATENCY), Delay::new(MAX_LATENCY)],
+            limiter: Limiter::new(),
+            output_quantizers: [Quantizer::new(2), Quantizer::new(3)],
+            smoothed,
+            learn: None,
+            bypass_delays: [
+                Delay::new(limiter::MAX_LATENCY),
+                Delay::new(limiter::MAX_LATENCY),
+            ],
+            wet_loudness: Loudness::new(),
+            dry_loudness: Loudness::new(),
+            bypass_mix: 0.0,
+            bypass_step: 1.0,
+            meters: Arc::new(Meters::new()),
+            parameters,
+            generation: None,
         }
     }
 
-    fn init(&mut self) {}
+    fn init(&mut self) {
+        self.smoothed = SmoothedParameters::new(&self.params, Smoothing::Linear, SMOOTHING_MS);
+        self.set_sample_rate(self.params.sample_rate.get());
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.params.sample_rate.set(rate);
+        self.params.mark_changed();
+        for channel in &mut self.channels {
+            channel.dc_blocker.set_cutoff(DC_BLOCKER_CUTOFF, rate);
+        }
+        self.limiter.set_sample_rate(rate);
+        self.wet_loudness.set_sample_rate(rate);
+        self.dry_loudness.set_sample_rate(rate);
+        self.smoothed.set_sample_rate(rate);
+        self.bypass_step = 1.0 / (BYPASS_FADE_MS / 1000.0 * rate);
+    }
 
     fn get_info(&self) -> Info {
         Info {
@@ -39,44 +174,45 @@ impl Plugin for Clipper {
             inputs: 2,
             // Two channel audio!
             outputs: 2,
+            preset_chunks: true,
+            initial_delay: initial_delay(self.latency()),
+            f64_precision: true,
             // For now, fill in the rest of our fields with `Default` info.
             ..Default::default()
         }
     }
 
-    fn can_do(&self, can_do: CanDo) -> Supported {
-        match can_do {
-            CanDo::Bypass => Supported::Yes,
-            _ => Supported::No,
-        }
+    // As in Median Filter, the host's bypass state never reaches us, so bypass
+    // isn't advertised. The Bypass parameter crossfades instead.
+    fn can_do(&self, _can_do: CanDo) -> Supported {
+        Supported::No
     }
 
     // Output audio given the current state of the VST
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        let params = Parameters::from(self.params.as_ref());
-        let wet_dry = params.wet_dry;
         let num_samples = buffer.samples();
-
+        let mut block = self.begin_block(num_samples);
         let (inputs, mut outputs) = buffer.split();
-        let left_input = &inputs[0];
-        let left_output = &mut outputs[0];
-
         for i in 0..num_samples {
-            let out = left_input[i] * params.pre_amplify;
-            let out = out.clamp(-params.clip_level, params.clip_level);
-            let out = out * params.post_amplify;
-            left_output[i] = left_input[i] * (1.0 - wet_dry) + out * wet_dry;
+            let input = [inputs[0][i] as f64, inputs[1][i] as f64];
+            let output = self.process_frame(&mut block, input);
+            outputs[0][i] = output[0] as f32;
+            outputs[1][i] = output[1] as f32;
         }
+        self.end_block(&block);
+    }
 
-        let right_input = &inputs[1];
-        let right_output = &mut outputs[1];
-
+    fn process_f64(&mut self, buffer: &mut AudioBuffer<f64>) {
+        let num_samples = buffer.samples();
+        let mut block = self.begin_block(num_samples);
+        let (inputs, mut outputs) = buffer.split();
         for i in 0..num_samples {
-            let out = right_input[i] * params.pre_amplify;
-            let out = out.clamp(-params.clip_level, params.clip_level);
-            let out = out * params.post_amplify;
-            right_output[i] = right_input[i] * (1.0 - wet_dry) + out * wet_dry;
+            let input = [inputs[0][i], inputs[1][i]];
+            let output = self.process_frame(&mut block, input);
+            outputs[0][i] = output[0];
+            outputs[1][i] = output[1];
         }
+        self.end_block(&block);
     }
 
     // The raw parameters exposed to the host
@@ -85,43 +221,1302 @@ impl Plugin for Clipper {
     }
 }
 
+/// The state of one channel of the clip stage.
+struct Channel {
+    // Keeps the lows out of the clip stage, with up to four second order
+    // sections.
+    low_cut: [Biquad<f64>; 4],
+    // The lows which were cut, delayed to line up with the clipped signal.
+    lows: Delay<f64>,
+    // Boosts or cuts the highs before clipping, and undoes it afterwards.
+    pre_emphasis: Biquad<f64>,
+    de_emphasis: Biquad<f64>,
+    oversampler: Oversampler<f64>,
+    // Splits the signal into bands in multiband mode. This runs at the
+    // oversampled rate.
+    crossover: Crossover,
+    // The state of the character model for each band, where only the first
+    // is used outside of multiband mode.
+    character_states: [CharacterState; 3],
+    // How far the character models' state moves towards the signal each
+    // oversampled sample.
+    character_coefficient: f64,
+    // The recent inputs to the second clip stage
+    second_history: History,
+    // The envelope of the input, relative to the clip level, which the
+    // dynamic threshold follows
+    envelope: f64,
+    attack: f64,
+    release: f64,
+    // Detects overs between samples for the clip counter
+    true_peak: TruePeak,
+    dc_blocker: DcBlocker<f64>,
+    quantizer: Quantizer,
+}
+
+impl Channel {
+    fn new(seed: u32) -> Channel {
+        Channel {
+            low_cut: [Biquad::default(); 4],
+            lows: Delay::new(MAX_LATENCY),
+            pre_emphasis: Biquad::default(),
+            de_emphasis: Biquad::default(),
+            oversampler: Oversampler::default(),
+            crossover: Crossover::new(),
+            character_states: [CharacterState::default(); 3],
+            second_history: History::default(),
+            character_coefficient: 0.0,
+            envelope: 0.0,
+            attack: 0.0,
+            release: 0.0,
+            true_peak: TruePeak::new(),
+            dc_blocker: DcBlocker::default(),
+            quantizer: Quantizer::new(seed),
+        }
+    }
+
+    fn set_params(&mut self, params: &Parameters, sample_rate: f32) {
+        for (filter, q) in self.low_cut.iter_mut().zip(params.low_cut_slope.qs()) {
+            filter.set_highpass(params.low_cut_frequency, *q, sample_rate);
+        }
+        self.pre_emphasis
+            .set_high_shelf(EMPHASIS_FREQUENCY, params.emphasis, sample_rate);
+        self.de_emphasis
+            .set_high_shelf(EMPHASIS_FREQUENCY, -params.emphasis, sample_rate);
+        self.oversampler
+            .set_params(params.oversample, params.quality.taps());
+        let coefficient = |time: f32, rate: f32| 1.0 - (-1.0 / (time * rate) as f64).exp();
+        self.attack = coefficient(DYNAMICS_ATTACK, sample_rate);
+        self.release = coefficient(DYNAMICS_RELEASE, sample_rate);
+        let oversampled_rate = sample_rate * params.oversample as f32;
+        self.character_coefficient = coefficient(CHARACTER_TIME, oversampled_rate);
+        if params.multiband {
+            self.crossover.set_frequencies(
+                params.low_crossover,
+                params.high_crossover,
+                oversampled_rate,
+            );
+        } else {
+            self.crossover.reset();
+        }
+    }
+
+    /// Return the clip level for `x` after following its envelope. With
+    /// positive Dynamics, the clip level falls as the envelope rises towards
+    /// it, so sustained sounds are clipped harder than transients, which get
+    /// through before the envelope catches up. Negative Dynamics does the
+    /// opposite.
+    fn threshold(&mut self, x: f64, clip_level: f64, params: &Parameters) -> f64 {
+        let level = x.abs() / clip_level;
+        let coefficient = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope += (level - self.envelope) * coefficient;
+        let dynamics = -params.dynamics * DYNAMICS_RANGE * self.envelope.min(1.0) as f32;
+        clip_level * db_to_gain(dynamics) as f64
+    }
+
+    /// Clip `x`, which has already had the pre gain applied, to `clip_level`.
+    /// `bands` is the linear drive and clip level of each band in multiband
+    /// mode, and `second` is the second clip stage, if it is on.
+    fn process(
+        &mut self,
+        x: f64,
+        clip_level: f64,
+        params: &Parameters,
+        bands: &[(f64, f64); 3],
+        second: Option<SecondStage>,
+    ) -> f64 {
+        let (x, lows) = if params.low_cut {
+            let slope = params.low_cut_slope;
+            let highs = self.low_cut[..slope.qs().len()]
+                .iter_mut()
+                .fold(x, |x, filter| filter.next(x));
+            (highs, x - highs)
+        } else {
+            (x, 0.0)
+        };
+        let lows = self.lows.next(lows, self.oversampler.latency());
+        let x = self.pre_emphasis.next(x);
+        let coefficient = self.character_coefficient;
+        let clip = |x: f64, level: f64, state: &mut CharacterState| {
+            saturate(params, x, level, state, coefficient)
+        };
+        let crossover = &mut self.crossover;
+        let states = &mut self.character_states;
+        let second_history = &mut self.second_history;
+        let out = self.oversampler.process(x, |x| {
+            let x = match second {
+                Some(second) => x / second.split,
+                None => x,
+            };
+            let y = if params.multiband {
+                crossover
+                    .split(x)
+                    .iter()
+                    .zip(bands)
+                    .zip(states.iter_mut())
+                    .map(|((band, (drive, level)), state)| {
+                        clip(band * drive, clip_level * level, state)
+                    })
+                    .sum()
+            } else {
+                clip(x, clip_level, &mut states[0])
+            };
+            match second {
+                Some(second) => second.process(params, y, clip_level, second_history),
+                None => y,
+            }
+        });
+        let out = self.de_emphasis.next(out);
+        let out = if params.low_cut && params.sum_lows {
+            out + lows
+        } else {
+            out
+        };
+        let out = if params.dc_block {
+            self.dc_blocker.next(out)
+        } else {
+            self.dc_blocker.reset();
+            out
+        };
+        match params.bit_depth {
+            Some(bits) => self.quantizer.quantize(out, bits, params.dither),
+            None => out,
+        }
+    }
+}
+
+/// Clip `x` to within about `level` with the selected Shape or Character.
+/// `state` and `coefficient` are used by the Characters.
+fn saturate(
+    params: &Parameters,
+    x: f64,
+    level: f64,
+    state: &mut CharacterState,
+    coefficient: f64,
+) -> f64 {
+    match params.character {
+        Character::Clean => {
+            // The bias makes the curve asymmetric. The offset it leaves on
+            // silence is subtracted right away, and the DC blocker removes
+            // the rest.
+            let bias = params.bias as f64 * level;
+            clip_shape(params, params.shape, x + bias, level, &mut state.history)
+                - params.shape.clip(bias, level, params.knee as f64)
+        }
+        character => character.process(state, x, level, coefficient),
+    }
+}
+
+/// Clip `x` to within `level` with `shape`, anti-aliased as set in `params`.
+fn clip_shape(params: &Parameters, shape: Shape, x: f64, level: f64, history: &mut History) -> f64 {
+    let knee = params.knee as f64;
+    if params.anti_alias == AntiAlias::Off || level <= 0.0 {
+        return shape.clip(x, level, knee);
+    }
+    let curve = NormalizedShape { shape, knee };
+    history.process(params.anti_alias, &curve, x / level) * level
+}
+
+/// Estimate the total harmonic distortion the clip stage adds to a sine wave
+/// at -6 dBFS, as the ratio of the level of its harmonics to the level of its
+/// fundamental. One period of the tone is run through the curve and its
+/// harmonics are measured with a DFT. Multiband mode, emphasis and the memory
+/// of the Characters are left out.
+fn harmonic_distortion(params: &Parameters, gains: &Gains) -> f32 {
+    let mut state = CharacterState::default();
+    let mut history = History::default();
+    let second = SecondStage::new(params, gains.pre_gain);
+    let mut harmonics = [(0.0f64, 0.0f64); THD_HARMONICS + 1];
+    for n in 0..THD_POINTS {
+        let phase = TAU * n as f64 / THD_POINTS as f64;
+        let x = 0.5 * phase.sin() * gains.pre_gain;
+        let y = match second {
+            Some(second) => {
+                let y = saturate(params, x / second.split, gains.clip_level, &mut state, 0.0);
+                second.process(params, y, gains.clip_level, &mut history)
+            }
+            None => saturate(params, x, gains.clip_level, &mut state, 0.0),
+        };
+        for (k, (re, im)) in harmonics.iter_mut().enumerate().skip(1) {
+            *re += y * (k as f64 * phase).cos();
+            *im += y * (k as f64 * phase).sin();
+        }
+    }
+    let power = |&(re, im): &(f64, f64)| re * re + im * im;
+    let fundamental = power(&harmonics[1]);
+    if fundamental <= 0.0 {
+        return 0.0;
+    }
+    let rest: f64 = harmonics[2..].iter().map(power).sum();
+    (rest / fundamental).sqrt() as f32
+}
+
+/// Convert a left and right pair into a mid and side pair, if `mid_side` is
+/// true.
+fn encode([left, right]: [f64; 2], mid_side: bool) -> [f64; 2] {
+    if mid_side {
+        [(left + right) / 2.0, (left - right) / 2.0]
+    } else {
+        [left, right]
+    }
+}
+
+/// Convert a mid and side pair back into a left and right pair, if
+/// `mid_side` is true.
+fn decode([mid, side]: [f64; 2], mid_side: bool) -> [f64; 2] {
+    if mid_side {
+        [mid + side, mid - side]
+    } else {
+        [mid, side]
+    }
+}
+
+/// The settings for a block of samples, and what was measured while
+/// processing it.
+struct Block {
+    params: Parameters,
+    num_samples: usize,
+    // The drive and clip level of each band, relative to the pre gain and
+    // clip level
+    bands: [(f64, f64); 3],
+    latency: usize,
+    ceiling: f64,
+    clipped: usize,
+    // The furthest past the clip level any sample went, as a ratio.
+    over: f64,
+    input_level: Measure,
+    output_level: Measure,
+}
+
+impl Clipper {
+    /// Work out the settings for the next `num_samples` samples. The filters
+    /// and the distortion estimate are only redone when the parameters have
+    /// changed.
+    fn begin_block(&mut self, num_samples: usize) -> Block {
+        if self.update_params() {
+            let params = &self.parameters;
+            let sample_rate = self.params.sample_rate.get();
+            for channel in &mut self.channels {
+                channel.set_params(params, sample_rate);
+            }
+            if !params.limiter {
+                self.limiter.reset();
+            }
+            let gains = Gains::new(params, |parameter| self.params.real(parameter));
+            self.meters
+                .set_distortion(harmonic_distortion(params, &gains));
+        }
+        let params = self.parameters;
+        self.smoothed.update(&self.params);
+
+        let bands = [
+            (params.low_drive, params.low_clip_level),
+            (params.mid_drive, params.mid_clip_level),
+            (params.high_drive, params.high_clip_level),
+        ]
+        .map(|(drive, clip_level)| (db_to_gain(drive) as f64, db_to_gain(clip_level) as f64));
+
+        Block {
+            num_samples,
+            bands,
+            latency: self.channels[0].oversampler.latency(),
+            ceiling: db_to_gain(params.ceiling) as f64,
+            clipped: 0,
+            over: 1.0,
+            input_level: Measure::default(),
+            output_level: Measure::default(),
+            params,
+        }
+    }
+
+    /// Return the output for the next stereo sample of `block`. Everything
+    /// runs at double precision, and only the meters are fed single precision
+    /// samples.
+    fn process_frame(&mut self, block: &mut Block, input: [f64; 2]) -> [f64; 2] {
+        let params = &block.params;
+        self.smoothed.advance();
+        let smoothed = &self.smoothed;
+        let gains = Gains::new(params, |parameter| smoothed.real(parameter));
+        block.input_level.add(input[0] as f32);
+        block.input_level.add(input[1] as f32);
+        let dry = [
+            self.delays[0].next(input[0], block.latency),
+            self.delays[1].next(input[1], block.latency),
+        ];
+
+        // The drive offsets are undone after clipping, so they only change
+        // how hard each channel is clipped.
+        let drives = gains.drives;
+        let mut wet = encode(
+            [input[0] * drives[0], input[1] * drives[1]],
+            params.mid_side,
+        );
+        // The dry signal at the level it went into the clip stage
+        let mut driven = encode(dry, params.mid_side);
+        let channel_gains = gains.channels(params.mid_side);
+        for (channel, &(pre_gain, clip_level)) in channel_gains.iter().enumerate() {
+            let x = wet[channel] * pre_gain;
+            let clip_level = self.channels[channel].threshold(x, clip_level, params);
+            let peak = self.channels[channel].true_peak.next(x);
+            if peak > clip_level {
+                block.clipped += 1;
+                block.over = block.over.max(peak / clip_level);
+            }
+            let second = SecondStage::new(params, pre_gain);
+            wet[channel] =
+                self.channels[channel].process(x, clip_level, params, &block.bands, second);
+            driven[channel] *= pre_gain;
+        }
+        let wet = decode(wet, params.mid_side);
+        let wet = [wet[0] / drives[0], wet[1] / drives[1]];
+        let driven = decode(driven, params.mid_side);
+
+        let wet_dry = self.smoothed.get(ParameterType::WetDry) as f64;
+        let mut out = [0.0; 2];
+        for channel in 0..2 {
+            out[channel] = if params.listen {
+                // Only what the clip stage changed
+                (wet[channel] - driven[channel]) * gains.post_gain
+            } else {
+                dry[channel] * (1.0 - wet_dry) + wet[channel] * gains.post_gain * wet_dry
+            };
+        }
+        if params.limiter {
+            out = self.limiter.process(out, block.ceiling);
+        }
+
+        // The dry signal, lined up with the output of the limiter
+        let limiter_latency = if params.limiter {
+            self.limiter.latency()
+        } else {
+            0
+        };
+        let bypassed = [
+            self.bypass_delays[0].next(dry[0], limiter_latency),
+            self.bypass_delays[1].next(dry[1], limiter_latency),
+        ];
+        // Both are always measured, so that switching to Match doesn't have
+        // to wait for the measurements to settle.
+        self.wet_loudness.add([out[0] as f32, out[1] as f32]);
+        self.dry_loudness
+            .add([bypassed[0] as f32, bypassed[1] as f32]);
+
+        if let Some(bits) = params.output_dither {
+            for (out, quantizer) in out.iter_mut().zip(&mut self.output_quantizers) {
+                *out = quantizer.quantize(*out, bits, true);
+            }
+        }
+
+        let target = if params.bypass == Bypass::Off {
+            0.0
+        } else {
+            1.0
+        };
+        self.bypass_mix = if self.bypass_mix < target {
+            (self.bypass_mix + self.bypass_step).min(target)
+        } else {
+            (self.bypass_mix - self.bypass_step).max(target)
+        };
+        if self.bypass_mix > 0.0 {
+            let gain = if params.bypass == Bypass::Match {
+                self.match_gain()
+            } else {
+                1.0
+            } as f64;
+            let mix = self.bypass_mix as f64;
+            for (out, bypassed) in out.iter_mut().zip(bypassed) {
+                *out += (bypassed * gain - *out) * mix;
+            }
+        }
+        block.output_level.add(out[0] as f32);
+        block.output_level.add(out[1] as f32);
+        out
+    }
+
+    /// The gain which brings the dry signal to the loudness of the processed
+    /// signal.
+    fn match_gain(&self) -> f32 {
+        let dry = self.dry_loudness.mean_square();
+        let wet = self.wet_loudness.mean_square();
+        let max_gain = db_to_gain(MAX_MATCH_GAIN);
+        if dry <= 0.0 {
+            return 1.0;
+        }
+        (wet / dry).sqrt().clamp(1.0 / max_gain, max_gain)
+    }
+
+    /// Publish what was measured while processing `block`.
+    fn end_block(&mut self, block: &Block) {
+        self.meters
+            .update(block.clipped, gain_to_db(block.over as f32));
+        self.meters.input.update(&block.input_level);
+        self.meters.output.update(&block.output_level);
+        self.learn(block);
+    }
+
+    /// Rebuild the parameters if any have changed since they were last built.
+    /// Returns true if they were rebuilt.
+    fn update_params(&mut self) -> bool {
+        let generation = self.params.generation();
+        if self.generation == Some(generation) {
+            return false;
+        }
+        self.generation = Some(generation);
+        self.parameters = Parameters::from(self.params.as_ref());
+        true
+    }
+
+    /// Listen to the input while Learn is on, and once enough has been heard,
+    /// set the pre gain so that the loudest peak lands on the clip level. This
+    /// runs on the audio thread, so the host hears about it later.
+    fn learn(&mut self, block: &Block) {
+        if !block.params.learn {
+            self.learn = None;
+            return;
+        }
+        let sample_rate = self.params.sample_rate.get();
+        let (remaining, peak) = self
+            .learn
+            .get_or_insert(((LEARN_SECONDS * sample_rate) as usize, 0.0));
+        *peak = peak.max(block.input_level.peak());
+        *remaining = remaining.saturating_sub(block.num_samples);
+        if *remaining > 0 {
+            return;
+        }
+
+        if *peak > 0.0 {
+            let pre_amplify = (block.params.clip_level - gain_to_db(*peak)).clamp(-24.0, 24.0);
+            self.params.set_from_audio(
+                ParameterType::PreAmp.range().to_normalized(pre_amplify),
+                ParameterType::PreAmp,
+            );
+        }
+        self.params.set_from_audio(0.0, ParameterType::Learn);
+        self.learn = None;
+    }
+
+    /// The current latency of the plugin, in samples.
+    fn latency(&self) -> usize {
+        let params = Parameters::from(self.params.as_ref());
+        let limiter = if params.limiter {
+            self.limiter.latency()
+        } else {
+            0
+        };
+        oversample::latency(params.oversample, params.quality.taps()) + limiter
+    }
+}
+
+#[derive(Clone, Copy)]
 struct Parameters {
+    /// The level the signal is clipped to, in dBTP, from -36 to 0
     clip_level: f32,
-    pre_amplify: f32,
-    post_amplify: f32,
-    wet_dry: f32,
+    shape: Shape,
+    /// The width of the soft knee of the Hard shape, relative to the clip
+    /// level, from 0 to 1
+    knee: f32,
+    /// How much the clip level follows the envelope of the input, from -1
+    /// to 1
+    dynamics: f32,
+    /// The saturation model, which replaces the Shape unless it is Clean
+    character: Character,
+    /// The offset added before clipping with the Shape, relative to the clip
+    /// level, from -0.5 to 0.5
+    bias: f32,
+    /// The gain of the high shelf before the clip stage, in decibels, from
+    /// -12 to +12. The shelf after the clip stage has the opposite gain, so
+    /// positive values clip the highs first and negative values the lows
+    emphasis: f32,
+    /// The oversampling factor of the clip stage, from 1 to 8
+    oversample: usize,
+    quality: Quality,
+    /// If true, a second clip stage follows the first
+    second_stage: bool,
+    second_shape: Shape,
+    /// The gain between the two clip stages, in decibels, from -12 to +12
+    second_drive: f32,
+    /// The clip level of the second stage relative to the first's, in
+    /// decibels, from -24 to 0
+    second_clip_level: f32,
+    /// How much of the pre gain is moved from before the first stage to
+    /// between the stages, from 0 to 1. At 0, the first stage does all of the
+    /// clipping it would on its own. Higher values clip each stage more
+    /// gently, while the level of the unclipped signal stays the same
+    stage_balance: f32,
+    /// How the Shape is anti-aliased at the clip stage's sample rate. This
+    /// only applies when the Character is Clean
+    anti_alias: AntiAlias,
+    /// If true, DC offset added by the clip stage is filtered out
+    dc_block: bool,
+    /// The bit depth the clipped signal is quantized to, from 4 to 16, or
+    /// None to leave it alone
+    bit_depth: Option<u32>,
+    /// If true, the quantizer adds TPDF dither
+    dither: bool,
+    /// If true, the post gain also compensates for the pre gain and clip level
+    auto_gain: bool,
+    /// If true, both channels are driven equally. Otherwise, each has its own
+    /// drive offset
+    link: bool,
+    /// If true, a lookahead limiter keeps the output under the ceiling
+    limiter: bool,
+    /// The true peak level the limiter keeps the output under, in dBTP, from
+    /// -12 to 0
+    ceiling: f32,
+    bypass: Bypass,
+    /// If true, the lows are filtered out before the clip stage
+    low_cut: bool,
+    /// The cutoff of the low cut, in Hz, from 20 to 300
+    low_cut_frequency: f32,
+    low_cut_slope: Slope,
+    /// If true, the lows which were filtered out are added back in after the
+    /// clip stage, so that only the rest of the signal is clipped
+    sum_lows: bool,
+    /// If true, the input is being listened to in order to set the pre gain.
+    /// This turns itself off when done
+    learn: bool,
+    /// The bit depth the output is dithered and quantized to, or None to
+    /// leave it alone
+    output_dither: Option<u32>,
+    /// If true, the signal is split into three bands which are clipped
+    /// separately
+    multiband: bool,
+    /// The crossover between the low and mid bands, in Hz, from 40 to 1000
+    low_crossover: f32,
+    /// The crossover between the mid and high bands, in Hz, from 1000 to 16000
+    high_crossover: f32,
+    /// The gain added to each band before clipping, in decibels, from -12
+    /// to +12
+    low_drive: f32,
+    mid_drive: f32,
+    high_drive: f32,
+    /// The clip level of each band, in decibels relative to the clip level,
+    /// from -24 to 0
+    low_clip_level: f32,
+    mid_clip_level: f32,
+    high_clip_level: f32,
+    /// If true, the mid and side channels are clipped instead of the left and
+    /// right channels. The mid channel uses the main pre gain and clip level.
+    /// The side channel has its own
+    mid_side: bool,
+    /// If true, only the difference between the clipped and dry signals is
+    /// output
+    listen: bool,
 }
 
 impl From<&RawParameters> for Parameters {
     fn from(params: &RawParameters) -> Self {
         Parameters {
-            wet_dry: params.wet_dry.get(),
-            clip_level: ease_in_expo(params.clip_level.get()),
-            pre_amplify: params.pre_amplify.get() * 16.0,
-            post_amplify: params.post_amplify.get() * 4.0,
+            link: params.link.get() > 0.5,
+            limiter: params.limiter.get() > 0.5,
+            ceiling: params.real(ParameterType::Ceiling),
+            learn: params.learn.get() > 0.5,
+            bypass: Bypass::from(params.bypass.get()),
+            low_cut: params.low_cut.get() > 0.5,
+            low_cut_frequency: params.real(ParameterType::LowCutFrequency),
+            low_cut_slope: Slope::from(params.low_cut_slope.get()),
+            sum_lows: params.sum_lows.get() > 0.5,
+            output_dither: output_dither_bits(params.real(ParameterType::OutputDither) as usize),
+            listen: params.listen.get() > 0.5,
+            mid_side: params.mid_side.get() > 0.5,
+            multiband: params.multiband.get() > 0.5,
+            low_crossover: params.real(ParameterType::LowCrossover),
+            high_crossover: params.real(ParameterType::HighCrossover),
+            low_drive: params.real(ParameterType::LowDrive),
+            mid_drive: params.real(ParameterType::MidDrive),
+            high_drive: params.real(ParameterType::HighDrive),
+            low_clip_level: params.real(ParameterType::LowClipLevel),
+            mid_clip_level: params.real(ParameterType::MidClipLevel),
+            high_clip_level: params.real(ParameterType::HighClipLevel),
+            clip_level: params.real(ParameterType::ClipLevel),
+            shape: Shape::from(params.shape.get()),
+            knee: params.knee.get(),
+            dynamics: params.real(ParameterType::Dynamics) / 100.0,
+            character: Character::from(params.character.get()),
+            bias: params.real(ParameterType::Bias) / 100.0,
+            emphasis: params.real(ParameterType::Emphasis),
+            oversample: 1 << params.real(ParameterType::Oversample) as usize,
+            quality: Quality::from(params.quality.get()),
+            anti_alias: AntiAlias::from(params.anti_alias.get()),
+            second_stage: params.second_stage.get() > 0.5,
+            second_shape: Shape::from(params.second_shape.get()),
+            second_drive: params.real(ParameterType::SecondDrive),
+            second_clip_level: params.real(ParameterType::SecondClipLevel),
+            stage_balance: params.stage_balance.get(),
+            dc_block: params.dc_block.get() > 0.5,
+            bit_depth: bit_depth_bits(params.real(ParameterType::BitDepth) as usize),
+            dither: params.dither.get() > 0.5,
+            auto_gain: params.auto_gain.get() > 0.5,
+        }
+    }
+}
+
+/// The gains applied around the clip stage, as linear amplitudes.
+#[derive(Debug, Clone, Copy)]
+struct Gains {
+    pre_gain: f64,
+    clip_level: f64,
+    side_pre_gain: f64,
+    side_clip_level: f64,
+    /// The drive offsets of the left and right channels
+    drives: [f64; 2],
+    post_gain: f64,
+}
+
+impl Gains {
+    /// The pre gain and clip level of each channel. In mid/side mode, the
+    /// side channel has its own.
+    fn channels(&self, mid_side: bool) -> [(f64, f64); 2] {
+        let main = (self.pre_gain, self.clip_level);
+        if mid_side {
+            [main, (self.side_pre_gain, self.side_clip_level)]
+        } else {
+            [main, main]
+        }
+    }
+
+    /// The gains for `params`, where `level` returns the real value of each
+    /// of the levels, so that they can be smoothed.
+    fn new(params: &Parameters, level: impl Fn(ParameterType) -> f32) -> Gains {
+        let pre_amplify = level(ParameterType::PreAmp);
+        let clip_level = level(ParameterType::ClipLevel);
+        let post_amplify = if params.auto_gain {
+            level(ParameterType::PostAmp) + auto_gain(pre_amplify, clip_level)
+        } else {
+            level(ParameterType::PostAmp)
+        };
+        let gain = |db: f32| db_to_gain(db) as f64;
+        Gains {
+            pre_gain: gain(pre_amplify),
+            clip_level: gain(clip_level),
+            side_pre_gain: gain(level(ParameterType::SidePreAmp)),
+            side_clip_level: gain(level(ParameterType::SideClipLevel)),
+            drives: if params.link {
+                [1.0, 1.0]
+            } else {
+                [
+                    gain(level(ParameterType::LeftDrive)),
+                    gain(level(ParameterType::RightDrive)),
+                ]
+            },
+            post_gain: gain(post_amplify),
+        }
+    }
+}
+
+/// The gains around the second clip stage of one channel.
+#[derive(Debug, Clone, Copy)]
+struct SecondStage {
+    /// The part of the pre gain which is moved from before the first stage to
+    /// between the stages
+    split: f64,
+    /// The gain between the stages
+    drive: f64,
+    /// The clip level of the second stage, relative to the first's
+    level: f64,
+}
+
+impl SecondStage {
+    /// The second stage for a channel with the given pre gain, or None if it
+    /// is off.
+    fn new(params: &Parameters, pre_gain: f64) -> Option<SecondStage> {
+        if !params.second_stage {
+            return None;
+        }
+        Some(SecondStage {
+            split: pre_gain.powf(params.stage_balance as f64),
+            drive: db_to_gain(params.second_drive) as f64,
+            level: db_to_gain(params.second_clip_level) as f64,
+        })
+    }
+
+    /// Clip `x`, the output of the first stage, whose clip level was
+    /// `clip_level`.
+    fn process(&self, params: &Parameters, x: f64, clip_level: f64, history: &mut History) -> f64 {
+        let x = x * self.split * self.drive;
+        clip_shape(
+            params,
+            params.second_shape,
+            x,
+            clip_level * self.level,
+            history,
+        )
+    }
+}
+
+/// The gain, in decibels, which roughly undoes the change in loudness from
+/// `pre_amplify` and `clip_level`. Below the clip level, the signal is only
+/// amplified, so this undoes the pre gain. Past it, the peaks stay at the clip
+/// level but the signal gets denser, so only half of the extra drive is undone.
+fn auto_gain(pre_amplify: f32, clip_level: f32) -> f32 {
+    let drive = pre_amplify - clip_level;
+    if drive <= 0.0 {
+        -pre_amplify
+    } else {
+        -clip_level - drive / 2.0
+    }
+}
+
+/// Rounds a signal to a lower bit depth.
+struct Quantizer {
+    rng: Rng,
+}
+
+impl Quantizer {
+    fn new(seed: u32) -> Quantizer {
+        Quantizer {
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Round `x` to the nearest of the levels a signed `bits`-bit integer can
+    /// hold, where full scale is 1.0. With `dither`, triangular noise one level
+    /// wide is added first, which trades the distortion for a noise floor.
+    fn quantize(&mut self, x: f64, bits: u32, dither: bool) -> f64 {
+        let levels = (1u32 << (bits - 1)) as f64;
+        let noise = if dither {
+            (self.rng.next_f32() - self.rng.next_f32()) as f64
+        } else {
+            0.0
+        };
+        (x * levels + noise).round() / levels
+    }
+}
+
+/// The transfer curve used to clip the signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    /// Clamp the signal to the clip level, with an optional soft knee.
+    Hard,
+    Tanh,
+    /// A cubic curve which reaches the clip level at the clip level.
+    Cubic,
+    Arctan,
+    /// A sine curve which folds the signal back down past the clip level,
+    /// instead of flattening it.
+    SineFold,
+}
+
+impl Shape {
+    /// Clip `x` to within `level`. `knee` only affects the Hard shape.
+    fn clip(&self, x: f64, level: f64, knee: f64) -> f64 {
+        if level <= 0.0 {
+            return 0.0;
         }
+        // Each curve has a slope of 1 at zero and flattens out at 1.
+        let x = x / level;
+        let y = match self {
+            Shape::Hard => soft_knee(x, knee),
+            Shape::Tanh => x.tanh(),
+            Shape::Cubic => {
+                let x = x.clamp(-1.0, 1.0);
+                1.5 * x - 0.5 * x * x * x
+            }
+            Shape::Arctan => (x * FRAC_PI_2).atan() * FRAC_2_PI,
+            Shape::SineFold => (x * FRAC_PI_2).sin(),
+        };
+        y * level
     }
 }
 
-macro_rules! table {
-    ($macro:ident) => {
-        $macro! {
-        //  RawParameter identifier, ParameterType identifier
-            RawParameters,          ParameterType;
-        //  variant      field_name     name             idx  default    strings
-            WetDry,      wet_dry,       "Wet/Dry",       0,   1.0,       |x: f32| make_strings(x * 100.0, "% Wet");
-            PreAmp,      pre_amplify,   "Pre-Amplify",   1,   0.125,     |x: f32| make_strings(x * 100.0, "%");
-            ClipLevel,   clip_level,    "Clip Level",    2,   0.5,       |x: f32| make_strings(x, "");
-            PostAmp,     post_amplify,  "Post-Amplify",  3,   0.25,      |x: f32| make_strings(x * 100.0, "%");
+/// A Shape with a clip level of 1.0, along with its antiderivatives for
+/// anti-aliasing. Each antiderivative is zero at zero.
+struct NormalizedShape {
+    shape: Shape,
+    knee: f64,
+}
+
+impl Antiderivatives for NormalizedShape {
+    fn curve(&self, x: f64) -> f64 {
+        self.shape.clip(x, 1.0, self.knee)
+    }
+
+    fn first(&self, x: f64) -> f64 {
+        // Every curve is odd, so its first antiderivative is even.
+        let x = x.abs();
+        match self.shape {
+            Shape::Hard => {
+                let (start, end) = (1.0 - self.knee, 1.0 + self.knee);
+                if x <= start {
+                    x * x / 2.0
+                } else if x >= end {
+                    end * end / 2.0 - 2.0 * self.knee * self.knee / 3.0 + (x - end)
+                } else {
+                    x * x / 2.0 - (x - start).powi(3) / (12.0 * self.knee)
+                }
+            }
+            // ln(cosh(x)), written so that it doesn't overflow
+            Shape::Tanh => x + (-2.0 * x).exp().ln_1p() - LN_2,
+            Shape::Cubic => {
+                if x <= 1.0 {
+                    0.75 * x * x - 0.125 * x.powi(4)
+                } else {
+                    0.625 + (x - 1.0)
+                }
+            }
+            Shape::Arctan => {
+                let a = FRAC_PI_2;
+                (x * (a * x).atan() - (a * a * x * x).ln_1p() / (2.0 * a)) / a
+            }
+            Shape::SineFold => {
+                let a = FRAC_PI_2;
+                (1.0 - (a * x).cos()) / a
+            }
         }
+    }
+
+    fn second(&self, x: f64) -> Option<f64> {
+        // The second antiderivative is odd again.
+        let sign = x.signum();
+        let x = x.abs();
+        let y = match self.shape {
+            Shape::Hard => {
+                let (start, end) = (1.0 - self.knee, 1.0 + self.knee);
+                if x <= start {
+                    x.powi(3) / 6.0
+                } else if x >= end {
+                    let over = x - end;
+                    end.powi(3) / 6.0 - self.knee.powi(3) / 3.0
+                        + self.first(end) * over
+                        + over * over / 2.0
+                } else {
+                    x.powi(3) / 6.0 - (x - start).powi(4) / (48.0 * self.knee)
+                }
+            }
+            // This needs the dilogarithm.
+            Shape::Tanh => return None,
+            Shape::Cubic => {
+                if x <= 1.0 {
+                    0.25 * x.powi(3) - 0.025 * x.powi(5)
+                } else {
+                    let over = x - 1.0;
+                    0.225 + 0.625 * over + over * over / 2.0
+                }
+            }
+            Shape::Arctan => {
+                let a = FRAC_PI_2;
+                let atan = (a * x).atan();
+                let log = (a * a * x * x).ln_1p();
+                (((a * a * x * x + 1.0) * atan - a * x) / (2.0 * a * a) - x * log / (2.0 * a)
+                    + x / a
+                    - atan / (a * a))
+                    / a
+            }
+            Shape::SineFold => {
+                let a = FRAC_PI_2;
+                (x - (a * x).sin() / a) / a
+            }
+        };
+        Some(sign * y)
+    }
+}
+
+/// Clamp `x` to within 1.0, rounding off the corner over a region `knee` wide
+/// on either side of it. The curve is a parabola within the knee, so that its
+/// slope runs smoothly from 1 to 0.
+fn soft_knee(x: f64, knee: f64) -> f64 {
+    let start = 1.0 - knee;
+    let magnitude = x.abs();
+    let y = if magnitude <= start {
+        magnitude
+    } else if magnitude >= 1.0 + knee {
+        1.0
+    } else {
+        let over = magnitude - start;
+        magnitude - over * over / (4.0 * knee)
     };
+    y.copysign(x)
+}
+
+impl From<f32> for Shape {
+    fn from(x: f32) -> Self {
+        match (x * 5.0) as usize {
+            0 => Shape::Hard,
+            1 => Shape::Tanh,
+            2 => Shape::Cubic,
+            3 => Shape::Arctan,
+            _ => Shape::SineFold,
+        }
+    }
+}
+
+impl std::fmt::Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shape::Hard => write!(f, "Hard"),
+            Shape::Tanh => write!(f, "Tanh"),
+            Shape::Cubic => write!(f, "Cubic"),
+            Shape::Arctan => write!(f, "Arctan"),
+            Shape::SineFold => write!(f, "Sine Fold"),
+        }
+    }
+}
+
+/// A saturation model with memory, used instead of the Shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Character {
+    /// Use the Shape.
+    Clean,
+    Diode,
+    Tube,
+    Tape,
+}
+
+/// What a Character, or the anti-aliased Shape, remembers between samples.
+#[derive(Debug, Clone, Copy, Default)]
+struct CharacterState {
+    // The recent inputs to the Shape, relative to the clip level
+    history: History,
+    // A slow average of the signal's magnitude, relative to the clip level
+    envelope: f64,
+    last_input: f64,
+    // The smoothed direction the signal is moving in, from -1 to 1
+    direction: f64,
+}
+
+impl Character {
+    /// Saturate `x` to within about `level`. The state moves `coefficient` of
+    /// the way towards the signal each sample.
+    fn process(&self, state: &mut CharacterState, x: f64, level: f64, coefficient: f64) -> f64 {
+        if level <= 0.0 {
+            return 0.0;
+        }
+        let x = x / level;
+        state.envelope += (x.abs() - state.envelope) * coefficient;
+        let y = match self {
+            Character::Clean => x,
+            Character::Diode => {
+                // Diodes conduct more sharply one way than the other, and the
+                // charge on the coupling capacitor shifts the bias as the
+                // level rises.
+                let bias = -0.25 * state.envelope.min(1.0);
+                diode(x + bias) - diode(bias)
+            }
+            Character::Tube => {
+                // A triode biased off-center, whose bias sags on loud signals
+                // as grid current flows.
+                let bias = 0.25 * (1.0 - state.envelope.min(1.0));
+                (x + bias).tanh() - bias.tanh()
+            }
+            Character::Tape => {
+                // The curve lags behind the direction the signal moves in, a
+                // rough stand-in for magnetic hysteresis.
+                let direction = if x > state.last_input {
+                    1.0
+                } else if x < state.last_input {
+                    -1.0
+                } else {
+                    0.0
+                };
+                state.last_input = x;
+                state.direction += (direction - state.direction) * coefficient;
+                (x - 0.1 * state.direction).tanh()
+            }
+        };
+        y * level
+    }
+}
+
+/// An asymmetric exponential curve with a slope of 1 at zero, which flattens
+/// out at 1 for positive inputs and at -0.5 for negative ones.
+fn diode(x: f64) -> f64 {
+    if x >= 0.0 {
+        1.0 - (-x).exp()
+    } else {
+        ((2.0 * x).exp() - 1.0) / 2.0
+    }
+}
+
+impl From<f32> for Character {
+    fn from(x: f32) -> Self {
+        match (x * 4.0) as usize {
+            0 => Character::Clean,
+            1 => Character::Diode,
+            2 => Character::Tube,
+            _ => Character::Tape,
+        }
+    }
+}
+
+impl std::fmt::Display for Character {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Character::Clean => write!(f, "Clean"),
+            Character::Diode => write!(f, "Diode"),
+            Character::Tube => write!(f, "Tube"),
+            Character::Tape => write!(f, "Tape"),
+        }
+    }
+}
+
+/// The steepness of the low cut filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slope {
+    Db12,
+    Db24,
+    Db48,
+}
+
+impl Slope {
+    /// The Q of each second order section of a Butterworth filter with this
+    /// slope.
+    fn qs(&self) -> &'static [f32] {
+        match self {
+            Slope::Db12 => &[FRAC_1_SQRT_2],
+            Slope::Db24 => &[0.5412, 1.3066],
+            Slope::Db48 => &[0.5098, 0.6013, 0.9000, 2.5629],
+        }
+    }
+}
+
+impl From<f32> for Slope {
+    fn from(x: f32) -> Self {
+        match (x * 3.0) as usize {
+            0 => Slope::Db12,
+            1 => Slope::Db24,
+            _ => Slope::Db48,
+        }
+    }
+}
+
+impl std::fmt::Display for Slope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Slope::Db12 => write!(f, "12 dB/oct"),
+            Slope::Db24 => write!(f, "24 dB/oct"),
+            Slope::Db48 => write!(f, "48 dB/oct"),
+        }
+    }
+}
+
+/// How the plugin is bypassed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bypass {
+    Off,
+    /// Output the dry signal.
+    On,
+    /// Output the dry signal at the loudness of the processed signal, so
+    /// that comparing the two isn't skewed by the loudness clipping adds.
+    Match,
+}
+
+impl From<f32> for Bypass {
+    fn from(x: f32) -> Self {
+        match (x * 3.0) as usize {
+            0 => Bypass::Off,
+            1 => Bypass::On,
+            _ => Bypass::Match,
+        }
+    }
+}
+
+impl std::fmt::Display for Bypass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bypass::Off => write!(f, "Off"),
+            Bypass::On => write!(f, "On"),
+            Bypass::Match => write!(f, "Match"),
+        }
+    }
 }
 
-impl ParameterType {
-    pub const COUNT: usize = 4;
+/// The length of the oversampling filters. Longer filters remove more
+/// aliasing, but add more latency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quality {
+    Low,
+    Medium,
+    High,
 }
 
-impl_all! {RawParameters, ParameterType, table}
+impl Quality {
+    /// The number of taps in each halfband filter.
+    fn taps(&self) -> usize {
+        match self {
+            Quality::Low => 17,
+            Quality::Medium => 33,
+            Quality::High => 65,
+        }
+    }
+}
+
+impl From<f32> for Quality {
+    fn from(x: f32) -> Self {
+        match (x * 3.0) as usize {
+            0 => Quality::Low,
+            1 => Quality::Medium,
+            _ => Quality::High,
+        }
+    }
+}
+
+impl std::fmt::Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quality::Low => write!(f, "Low"),
+            Quality::Medium => write!(f, "Medium"),
+            Quality::High => write!(f, "High"),
+        }
+    }
+}
+
+/// The bit depth selected by option `index` of the Bit Depth parameter, where
+/// the first option is off.
+fn bit_depth_bits(index: usize) -> Option<u32> {
+    match index {
+        0 => None,
+        index => Some(index as u32 + MIN_BIT_DEPTH - 1),
+    }
+}
+
+/// The bit depth selected by option `index` of the Output Dither parameter,
+/// where the first option is off.
+fn output_dither_bits(index: usize) -> Option<u32> {
+    index
+        .checked_sub(1)
+        .and_then(|index| OUTPUT_DITHER_DEPTHS.get(index).copied())
+}
+
+/// Label option `index` of the Oversampling parameter with its factor.
+fn oversample_label(index: usize, _count: usize) -> String {
+    format!("{}x", 1 << index)
+}
+
+fn bits_label(bits: Option<u32>) -> String {
+    match bits {
+        Some(bits) => format!("{} bits", bits),
+        None => "Off".to_string(),
+    }
+}
+
+/// The raw parameter values that a host DAW will set and modify.
+/// These are unscaled and are always in the [0.0, 1.0] range
+#[derive(VstParameters)]
+#[parameters(parameter_type = ParameterType)]
+pub struct RawParameters {
+    #[parameter(name = "Wet/Dry", default = 1.0, range = Range::linear(0.0, 100.0, "% Wet"))]
+    #[smoothed]
+    wet_dry: AtomicFloat,
+    #[parameter(variant = PreAmp, name = "Pre-Amplify", default = 0.625, range = Range::linear(-24.0, 24.0, "dB"))]
+    #[smoothed]
+    pre_amplify: AtomicFloat,
+    #[parameter(name = "Clip Level", default = 30.0 / 36.0, range = Range::linear(-36.0, 0.0, "dBTP"))]
+    #[smoothed]
+    clip_level: AtomicFloat,
+    #[parameter(variant = PostAmp, name = "Post-Amplify", default = 0.5, range = Range::linear(-24.0, 24.0, "dB"))]
+    #[smoothed]
+    post_amplify: AtomicFloat,
+    #[parameter(name = "Shape", default = 0.0, range = Range::choice(5, choice_label::<Shape>))]
+    shape: AtomicFloat,
+    #[parameter(name = "Oversampling", default = 0.0, range = Range::choice(OVERSAMPLE_FACTORS, oversample_label))]
+    oversample: AtomicFloat,
+    #[parameter(name = "Quality", default = 0.5, range = Range::choice(3, choice_label::<Quality>))]
+    quality: AtomicFloat,
+    #[parameter(name = "DC Blocker", default = 1.0, range = Range::toggle("OFF", "ON"))]
+    dc_block: AtomicFloat,
+    #[parameter(name = "Auto-Gain", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    auto_gain: AtomicFloat,
+    #[parameter(name = "Bit Depth", default = 0.0, range = Range::choice(BIT_DEPTHS, |index, _| bits_label(bit_depth_bits(index))))]
+    bit_depth: AtomicFloat,
+    #[parameter(name = "Dither", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    dither: AtomicFloat,
+    #[parameter(name = "Knee", default = 0.0, range = Range::linear(0.0, 100.0, "%"))]
+    knee: AtomicFloat,
+    #[parameter(name = "Delta Listen", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    listen: AtomicFloat,
+    #[parameter(name = "Multiband", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    multiband: AtomicFloat,
+    #[parameter(name = "Low Crossover", default = 0.5, range = Range::log(40.0, 1000.0, "Hz"))]
+    low_crossover: AtomicFloat,
+    #[parameter(name = "High Crossover", default = 0.5, range = Range::log(1000.0, 16000.0, "Hz"))]
+    high_crossover: AtomicFloat,
+    #[parameter(name = "Low Drive", default = 0.5, range = Range::linear(-12.0, 12.0, "dB"))]
+    low_drive: AtomicFloat,
+    #[parameter(name = "Mid Drive", default = 0.5, range = Range::linear(-12.0, 12.0, "dB"))]
+    mid_drive: AtomicFloat,
+    #[parameter(name = "High Drive", default = 0.5, range = Range::linear(-12.0, 12.0, "dB"))]
+    high_drive: AtomicFloat,
+    #[parameter(name = "Low Clip Level", default = 1.0, range = Range::linear(-24.0, 0.0, "dB"))]
+    low_clip_level: AtomicFloat,
+    #[parameter(name = "Mid Clip Level", default = 1.0, range = Range::linear(-24.0, 0.0, "dB"))]
+    mid_clip_level: AtomicFloat,
+    #[parameter(name = "High Clip Level", default = 1.0, range = Range::linear(-24.0, 0.0, "dB"))]
+    high_clip_level: AtomicFloat,
+    #[parameter(name = "Mid/Side", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    mid_side: AtomicFloat,
+    #[parameter(variant = SidePreAmp, name = "Side Pre-Amplify", default = 0.625, range = Range::linear(-24.0, 24.0, "dB"))]
+    #[smoothed]
+    side_pre_amplify: AtomicFloat,
+    #[parameter(name = "Side Clip Level", default = 30.0 / 36.0, range = Range::linear(-36.0, 0.0, "dBTP"))]
+    #[smoothed]
+    side_clip_level: AtomicFloat,
+    #[parameter(name = "Output Dither", default = 0.0, range = Range::choice(OUTPUT_DITHER_DEPTHS.len() + 1, |index, _| bits_label(output_dither_bits(index))))]
+    output_dither: AtomicFloat,
+    #[parameter(name = "Limiter", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    limiter: AtomicFloat,
+    #[parameter(name = "Ceiling", default = 11.0 / 12.0, range = Range::linear(-12.0, 0.0, "dBTP"))]
+    ceiling: AtomicFloat,
+    #[parameter(name = "Stereo Link", default = 1.0, range = Range::toggle("OFF", "ON"))]
+    link: AtomicFloat,
+    #[parameter(name = "Left Drive", default = 0.5, range = Range::linear(-12.0, 12.0, "dB"))]
+    #[smoothed]
+    left_drive: AtomicFloat,
+    #[parameter(name = "Right Drive", default = 0.5, range = Range::linear(-12.0, 12.0, "dB"))]
+    #[smoothed]
+    right_drive: AtomicFloat,
+    #[parameter(name = "Emphasis", default = 0.5, range = Range::linear(-12.0, 12.0, "dB"))]
+    emphasis: AtomicFloat,
+    #[parameter(name = "Bias", default = 0.5, range = Range::linear(-50.0, 50.0, "%"))]
+    bias: AtomicFloat,
+    #[parameter(name = "Character", default = 0.0, range = Range::choice(4, choice_label::<Character>))]
+    character: AtomicFloat,
+    #[parameter(name = "Dynamics", default = 0.5, range = Range::linear(-100.0, 100.0, "%"))]
+    dynamics: AtomicFloat,
+    #[parameter(name = "Learn", default = 0.0, range = Range::toggle("OFF", "Learning"))]
+    learn: AtomicFloat,
+    #[parameter(name = "Bypass", default = 0.0, range = Range::choice(3, choice_label::<Bypass>))]
+    bypass: AtomicFloat,
+    #[parameter(name = "Low Cut", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    low_cut: AtomicFloat,
+    #[parameter(name = "Low Cut Frequency", default = 0.5, range = Range::log(20.0, 300.0, "Hz"))]
+    low_cut_frequency: AtomicFloat,
+    #[parameter(name = "Low Cut Slope", default = 0.5, range = Range::choice(3, choice_label::<Slope>))]
+    low_cut_slope: AtomicFloat,
+    #[parameter(name = "Sum Lows", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    sum_lows: AtomicFloat,
+    #[parameter(name = "Anti-Aliasing", default = 0.0, range = Range::choice(3, choice_label::<AntiAlias>))]
+    anti_alias: AtomicFloat,
+    #[parameter(name = "Second Stage", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    second_stage: AtomicFloat,
+    #[parameter(name = "Second Shape", default = 0.0, range = Range::choice(5, choice_label::<Shape>))]
+    second_shape: AtomicFloat,
+    #[parameter(name = "Second Drive", default = 0.5, range = Range::linear(-12.0, 12.0, "dB"))]
+    second_drive: AtomicFloat,
+    #[parameter(name = "Second Clip Level", default = 1.0, range = Range::linear(-24.0, 0.0, "dB"))]
+    second_clip_level: AtomicFloat,
+    #[parameter(name = "Stage Balance", default = 0.5, range = Range::linear(0.0, 100.0, "%"))]
+    stage_balance: AtomicFloat,
+    /// The index of the most recently selected built-in program
+    current_preset: AtomicI32,
+    /// Bumped whenever a parameter changes. See `generation`.
+    generation: AtomicUsize,
+    /// Changes made on the audio thread which the host hasn't been told about
+    pending: PendingEdits,
+    /// The sample rate most recently given by the host
+    pub sample_rate: AtomicFloat,
+    /// The host callback, used for communicating with the VST host
+    pub host: HostCallback,
+}
+
+impl Automatable for ParameterType {
+    fn can_be_automated(&self) -> bool {
+        *self != ParameterType::Learn
+    }
+}
 
 // Export symbols for main
 vst::plugin_main!(Clipper);
@@ -0,0 +1,188 @@
+use common::delay::Delay;
+
+use crate::true_peak::{self, TruePeak};
+
+/// The longest lookahead, in samples.
+const MAX_LOOKAHEAD: usize = 512;
+/// How far ahead the limiter looks for peaks, in seconds.
+const LOOKAHEAD_SECONDS: f32 = 0.0015;
+/// How long the gain takes to recover after a peak, in seconds.
+const RELEASE_SECONDS: f32 = 0.1;
+
+/// The longest delay, in samples, the limiter can introduce.
+pub const MAX_LATENCY: usize = MAX_LOOKAHEAD + true_peak::LATENCY;
+/// The length of the buffers holding the most recent gains.
+const HISTORY: usize = MAX_LOOKAHEAD + 2;
+
+/// A stereo-linked lookahead true-peak limiter. The signal is delayed so that
+/// the gain can start falling before a peak arrives, which keeps the output at
+/// or below the ceiling without the distortion of clamping it.
+pub struct Limiter {
+    delays: [Delay<f64>; 2],
+    true_peaks: [TruePeak; 2],
+    // The gain each of the most recent samples needs to keep its true peak
+    // under the ceiling.
+    required: Vec<f64>,
+    // The gain envelope for each of the most recent samples, which is
+    // averaged to smooth out the attack.
+    envelopes: Vec<f64>,
+    // The index of the most recent sample in `required` and `envelopes`.
+    pos: usize,
+    envelope: f64,
+    // How many samples ahead of the output the gain starts to fall.
+    lookahead: usize,
+    // The coefficient the envelope releases by each sample.
+    release: f64,
+}
+
+impl Limiter {
+    pub fn new() -> Limiter {
+        Limiter {
+            delays: [Delay::new(MAX_LATENCY), Delay::new(MAX_LATENCY)],
+            true_peaks: [TruePeak::new(), TruePeak::new()],
+            required: vec![1.0; HISTORY],
+            envelopes: vec![1.0; HISTORY],
+            pos: 0,
+            envelope: 1.0,
+            lookahead: 0,
+            release: 0.0,
+        }
+    }
+
+    /// Set the lookahead and release times for `sample_rate`. This resets
+    /// the limiter.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.lookahead = ((LOOKAHEAD_SECONDS * sample_rate) as usize).min(MAX_LOOKAHEAD);
+        self.release = (-1.0 / (RELEASE_SECONDS * sample_rate) as f64).exp();
+        self.reset();
+    }
+
+    /// The delay added by the limiter, in samples.
+    pub fn latency(&self) -> usize {
+        self.lookahead + true_peak::LATENCY
+    }
+
+    /// Clear the delayed signal and release the gain.
+    pub fn reset(&mut self) {
+        for delay in &mut self.delays {
+            delay.reset();
+        }
+        for true_peak in &mut self.true_peaks {
+            true_peak.reset();
+        }
+        self.required.iter_mut().for_each(|x| *x = 1.0);
+        self.envelopes.iter_mut().for_each(|x| *x = 1.0);
+        self.envelope = 1.0;
+    }
+
+    /// Return the next output for the stereo input `[left, right]`, whose
+    /// true peak never goes above `ceiling`.
+    pub fn process(&mut self, [left, right]: [f64; 2], ceiling: f64) -> [f64; 2] {
+        self.pos = (self.pos + 1) % HISTORY;
+        let peak = self.true_peaks[0]
+            .next(left)
+            .max(self.true_peaks[1].next(right));
+        self.required[self.pos] = if peak > ceiling { ceiling / peak } else { 1.0 };
+
+        // Each true peak covers a sample and the points after it, so the
+        // sample before the one being output needs to be looked at as well.
+        let target = self
+            .recent(&self.required, self.lookahead + 2)
+            .fold(1.0f64, f64::min);
+        // Attack instantly, and let the average below smooth it out. By the
+        // time a peak reaches the output, every envelope being averaged is at
+        // or below the gain it needs.
+        self.envelope = if target < self.envelope {
+            target
+        } else {
+            target + (self.envelope - target) * self.release
+        };
+        self.envelopes[self.pos] = self.envelope;
+        let window = self.lookahead + 1;
+        let gain = self.recent(&self.envelopes, window).sum::<f64>() / window as f64;
+
+        let delay = self.latency();
+        [
+            self.delays[0].next(left, delay) * gain,
+            self.delays[1].next(right, delay) * gain,
+        ]
+    }
+
+    /// The `len` most recent values in `history`.
+    fn recent<'a>(&self, history: &'a [f64], len: usize) -> impl Iterator<Item = f64> + 'a {
+        let pos = self.pos;
+        (0..len).map(move |i| history[(pos + HISTORY - i) % HISTORY])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    use super::*;
+
+    /// A sine at a quarter of the sample rate, offset so that each sample
+    /// falls halfway between the peaks. The true peak is `amplitude`, about
+    /// 3 dB above any of the samples.
+    fn intersample_sine(amplitude: f64, i: usize) -> f64 {
+        amplitude * (FRAC_PI_2 * i as f64 + FRAC_PI_4).sin()
+    }
+
+    #[test]
+    fn true_peak_stays_under_ceiling() {
+        for &sample_rate in &[44100.0, 48000.0, 96000.0] {
+            for &ceiling in &[1.0, 0.5] {
+                let mut limiter = Limiter::new();
+                limiter.set_sample_rate(sample_rate);
+                let mut true_peaks = [TruePeak::new(), TruePeak::new()];
+                let mut max_peak = 0.0f64;
+                // Silence, then a burst which starts at full level, so the
+                // lookahead has to catch its first peak.
+                for i in 0..4000 {
+                    let x = if (1000..3000).contains(&i) {
+                        intersample_sine(4.0, i)
+                    } else {
+                        0.0
+                    };
+                    let [left, right] = limiter.process([x, -x], ceiling);
+                    max_peak = max_peak
+                        .max(true_peaks[0].next(left))
+                        .max(true_peaks[1].next(right));
+                }
+                // The gain changing from one sample to the next moves the
+                // peaks between them very slightly, by well under 0.01 dB.
+                assert!(
+                    max_peak <= ceiling * 1.0001,
+                    "{} Hz: true peak {} over a ceiling of {}",
+                    sample_rate,
+                    max_peak,
+                    ceiling
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn impulse_is_delayed_by_latency() {
+        for &sample_rate in &[44100.0, 48000.0, 96000.0, 192000.0] {
+            for &amplitude in &[0.5, 4.0] {
+                let mut limiter = Limiter::new();
+                limiter.set_sample_rate(sample_rate);
+                let output: Vec<f64> = (0..MAX_LATENCY * 2)
+                    .map(|i| {
+                        let x = if i == 0 { amplitude } else { 0.0 };
+                        limiter.process([x, x], 1.0)[0]
+                    })
+                    .collect();
+                let peak = (0..output.len())
+                    .max_by(|&a, &b| output[a].abs().partial_cmp(&output[b].abs()).unwrap())
+                    .unwrap();
+                assert_eq!(peak, limiter.latency(), "{} Hz", sample_rate);
+                if amplitude < 1.0 {
+                    // Nothing to limit, so the impulse comes out untouched.
+                    assert_eq!(output[peak], amplitude);
+                }
+            }
+        }
+    }
+}
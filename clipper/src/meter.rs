@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use vst::util::AtomicFloat;
+
+use common::{filter::Biquad, gain_to_db};
+
+/// The time constant of the loudness measurement, in seconds. BS.1770's
+/// short-term loudness averages over 3 seconds.
+const LOUDNESS_SECONDS: f32 = 3.0;
+
+/// The levels going through the plugin, and how hard the clip stage worked,
+/// over the most recently processed block. This is written by the audio
+/// thread and may be read from any other thread, such as an editor's.
+pub struct Meters {
+    clipped: AtomicUsize,
+    gain_reduction: AtomicFloat,
+    distortion: AtomicFloat,
+    pub input: Level,
+    pub output: Level,
+}
+
+impl Meters {
+    pub fn new() -> Meters {
+        Meters {
+            clipped: AtomicUsize::new(0),
+            gain_reduction: AtomicFloat::new(0.0),
+            distortion: AtomicFloat::new(0.0),
+            input: Level::new(),
+            output: Level::new(),
+        }
+    }
+
+    /// The number of samples, across both channels, whose true peak went past
+    /// the clip level in the last block.
+    pub fn clipped(&self) -> usize {
+        self.clipped.load(Ordering::Relaxed)
+    }
+
+    /// How far the loudest true peak in the last block went past the clip
+    /// level, in decibels. This is 0.0 if nothing was clipped.
+    pub fn gain_reduction(&self) -> f32 {
+        self.gain_reduction.get()
+    }
+
+    /// The total harmonic distortion the current settings add to a test tone,
+    /// as a ratio of the harmonics' level to the fundamental's.
+    pub fn distortion(&self) -> f32 {
+        self.distortion.get()
+    }
+
+    pub(crate) fn set_distortion(&self, distortion: f32) {
+        self.distortion.set(distortion);
+    }
+
+    pub(crate) fn update(&self, clipped: usize, gain_reduction: f32) {
+        self.clipped.store(clipped, Ordering::Relaxed);
+        self.gain_reduction.set(gain_reduction);
+    }
+}
+
+impl Default for Meters {
+    fn default() -> Meters {
+        Meters::new()
+    }
+}
+
+/// The peak and RMS level of a stereo signal, in dBFS.
+pub struct Level {
+    peak: AtomicFloat,
+    rms: AtomicFloat,
+}
+
+impl Level {
+    fn new() -> Level {
+        Level {
+            peak: AtomicFloat::new(f32::NEG_INFINITY),
+            rms: AtomicFloat::new(f32::NEG_INFINITY),
+        }
+    }
+
+    /// The loudest sample in either channel.
+    pub fn peak(&self) -> f32 {
+        self.peak.get()
+    }
+
+    /// The RMS level across both channels.
+    pub fn rms(&self) -> f32 {
+        self.rms.get()
+    }
+
+    pub(crate) fn update(&self, measure: &Measure) {
+        self.peak.set(gain_to_db(measure.peak));
+        let mean_square = if measure.count > 0 {
+            measure.sum_of_squares / measure.count as f32
+        } else {
+            0.0
+        };
+        self.rms.set(gain_to_db(mean_square.sqrt()));
+    }
+}
+
+/// Accumulates the peak and RMS level of a block, on the audio thread.
+#[derive(Debug, Default)]
+pub(crate) struct Measure {
+    peak: f32,
+    sum_of_squares: f32,
+    count: usize,
+}
+
+impl Measure {
+    /// The loudest sample added so far.
+    pub(crate) fn peak(&self) -> f32 {
+        self.peak
+    }
+
+    pub(crate) fn add(&mut self, x: f32) {
+        self.peak = self.peak.max(x.abs());
+        self.sum_of_squares += x * x;
+        self.count += 1;
+    }
+}
+
+/// Measures the short-term loudness of a stereo signal, roughly as BS.1770
+/// does. Each channel is K-weighted, and the sum of their mean squares is
+/// averaged over the last few seconds.
+pub(crate) struct Loudness {
+    // The K-weighting for each channel: a high shelf for the effect of the
+    // head, then a highpass
+    filters: [[Biquad; 2]; 2],
+    mean_square: f32,
+    coefficient: f32,
+}
+
+impl Loudness {
+    pub(crate) fn new() -> Loudness {
+        Loudness {
+            filters: [[Biquad::default(); 2]; 2],
+            mean_square: 0.0,
+            coefficient: 0.0,
+        }
+    }
+
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: f32) {
+        for [shelf, highpass] in &mut self.filters {
+            shelf.set_high_shelf(1500.0, 4.0, sample_rate);
+            highpass.set_highpass(38.0, 0.5, sample_rate);
+        }
+        self.coefficient = 1.0 - (-1.0 / (LOUDNESS_SECONDS * sample_rate)).exp();
+    }
+
+    pub(crate) fn add(&mut self, x: [f32; 2]) {
+        let mut sum = 0.0;
+        for (x, [shelf, highpass]) in x.iter().zip(&mut self.filters) {
+            let x = highpass.next(shelf.next(*x));
+            sum += x * x;
+        }
+        self.mean_square += (sum - self.mean_square) * self.coefficient;
+    }
+
+    /// The average power of the weighted signal.
+    pub(crate) fn mean_square(&self) -> f32 {
+        self.mean_square
+    }
+}
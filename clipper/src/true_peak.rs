@@ -0,0 +1,72 @@
+use std::f64::consts::PI;
+
+/// The number of points measured for each sample.
+const FACTOR: usize = 4;
+/// The length of each interpolation filter.
+const TAPS: usize = 12;
+/// The delay, in samples, before a peak is reported.
+pub const LATENCY: usize = TAPS / 2;
+
+/// Estimates the peaks of the continuous signal a series of samples
+/// represents, which can fall between samples and go above any of them. This
+/// interpolates between samples at four times the sample rate, which is what
+/// ITU-R BS.1770 specifies for true-peak meters.
+pub struct TruePeak {
+    // The interpolation filter for each point between samples
+    phases: [[f64; TAPS]; FACTOR],
+    // The most recent input samples, newest first
+    history: [f64; TAPS],
+}
+
+impl TruePeak {
+    pub fn new() -> TruePeak {
+        let mut phases = [[0.0; TAPS]; FACTOR];
+        for (phase, taps) in phases.iter_mut().enumerate() {
+            for (k, tap) in taps.iter_mut().enumerate() {
+                // The distance from the point being interpolated to the
+                // `k`th newest sample
+                let x = k as f64 - LATENCY as f64 + phase as f64 / FACTOR as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                // Blackman window, centered on the interpolated point
+                let window = 0.42
+                    + 0.5 * (PI * x / LATENCY as f64).cos()
+                    + 0.08 * (2.0 * PI * x / LATENCY as f64).cos();
+                *tap = sinc * window;
+            }
+            let sum: f64 = taps.iter().sum();
+            for tap in taps.iter_mut() {
+                *tap /= sum;
+            }
+        }
+        TruePeak {
+            phases,
+            history: [0.0; TAPS],
+        }
+    }
+
+    /// Add the next sample, and return the true peak magnitude from the
+    /// sample `LATENCY` samples ago up to the one after it.
+    pub fn next(&mut self, x: f64) -> f64 {
+        self.history.rotate_right(1);
+        self.history[0] = x;
+        self.phases
+            .iter()
+            .map(|taps| {
+                taps.iter()
+                    .zip(&self.history)
+                    .map(|(tap, x)| tap * x)
+                    .sum::<f64>()
+                    .abs()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// Clear the history.
+    pub fn reset(&mut self) {
+        self.history = [0.0; TAPS];
+    }
+}
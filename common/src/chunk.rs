@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// The version written into new chunks. Bump this when the meaning of a
+/// saved value changes, so that older chunks can be converted when loaded.
+pub const CHUNK_VERSION: u32 = 1;
+
+/// The plugin's state, as saved by hosts which store plugins as chunks
+/// instead of as individual parameter values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    pub version: u32,
+    /// The normalized value of each parameter, keyed by its field name so that
+    /// parameters can be added or reordered without breaking older chunks.
+    pub parameters: BTreeMap<String, f32>,
+    /// Values which aren't parameters, such as the result of a Learn pass,
+    /// keyed by field name. These aren't normalized.
+    #[serde(default)]
+    pub state: BTreeMap<String, f32>,
+}
+
+impl Chunk {
+    pub fn new(parameters: BTreeMap<String, f32>, state: BTreeMap<String, f32>) -> Chunk {
+        Chunk {
+            version: CHUNK_VERSION,
+            parameters,
+            state,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // A map of strings to floats always serializes.
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    /// Read a chunk written by `to_bytes`, or None if `data` isn't one or was
+    /// written by a newer version, whose values might mean something else.
+    pub fn from_bytes(data: &[u8]) -> Option<Chunk> {
+        serde_json::from_slice(data)
+            .ok()
+            .filter(|chunk: &Chunk| chunk.version <= CHUNK_VERSION)
+    }
+
+    /// The saved value of the parameter named `field_name`, or None if it
+    /// wasn't saved, such as when the chunk predates it.
+    pub fn get(&self, field_name: &str) -> Option<f32> {
+        self.parameters
+            .get(field_name)
+            .filter(|value| value.is_finite())
+            .map(|value| value.clamp(0.0, 1.0))
+    }
+
+    /// The saved value of the state named `field_name`, or None if it wasn't
+    /// saved.
+    pub fn get_state(&self, field_name: &str) -> Option<f32> {
+        self.state
+            .get(field_name)
+            .copied()
+            .filter(|value| value.is_finite())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk() -> Chunk {
+        let parameters = [("wet_dry".to_string(), 0.25), ("gain".to_string(), 1.0)];
+        let state = [("learned_threshold".to_string(), -42.5)];
+        Chunk::new(
+            parameters.iter().cloned().collect(),
+            state.iter().cloned().collect(),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let loaded = Chunk::from_bytes(&chunk().to_bytes()).unwrap();
+        assert_eq!(loaded.version, CHUNK_VERSION);
+        assert_eq!(loaded.parameters, chunk().parameters);
+        assert_eq!(loaded.state, chunk().state);
+    }
+
+    #[test]
+    fn ignores_unknown_fields() {
+        let data = br#"{"version": 1, "parameters": {"wet_dry": 0.5}, "editor": {"width": 640}}"#;
+        let loaded = Chunk::from_bytes(data).unwrap();
+        assert_eq!(loaded.get("wet_dry"), Some(0.5));
+    }
+
+    #[test]
+    fn missing_values_are_none() {
+        // Chunks from before state was saved don't have it at all.
+        let data = br#"{"version": 1, "parameters": {"wet_dry": 0.5}}"#;
+        let loaded = Chunk::from_bytes(data).unwrap();
+        assert_eq!(loaded.get("gain"), None);
+        assert_eq!(loaded.get_state("learned_threshold"), None);
+        assert!(Chunk::from_bytes(br#"{"version": 1}"#).is_none());
+    }
+
+    #[test]
+    fn clamps_parameters_but_not_state() {
+        let data = br#"{"version": 1, "parameters": {"wet_dry": 1.5}, "state": {"peak": -30.0}}"#;
+        let loaded = Chunk::from_bytes(data).unwrap();
+        assert_eq!(loaded.get("wet_dry"), Some(1.0));
+        assert_eq!(loaded.get_state("peak"), Some(-30.0));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut newer = chunk();
+        newer.version = CHUNK_VERSION + 1;
+        assert!(Chunk::from_bytes(&newer.to_bytes()).is_none());
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        assert!(Chunk::from_bytes(b"").is_none());
+        assert!(Chunk::from_bytes(&[0, 1, 2, 3]).is_none());
+    }
+}
@@ -0,0 +1,30 @@
+/// A fixed-capacity delay line.
+pub struct Delay<T = f32> {
+    buffer: Vec<T>,
+    // The index the next sample will be written to.
+    pos: usize,
+}
+
+impl<T: Copy + Default> Delay<T> {
+    pub fn new(max_delay: usize) -> Delay<T> {
+        Delay {
+            buffer: vec![T::default(); max_delay + 1],
+            pos: 0,
+        }
+    }
+
+    /// Write a sample to the delay line and return the sample from `delay`
+    /// samples ago. A `delay` of zero returns `input` unchanged.
+    pub fn next(&mut self, input: T, delay: usize) -> T {
+        let len = self.buffer.len();
+        self.buffer[self.pos] = input;
+        let sample = self.buffer[(self.pos + len - delay.min(len - 1)) % len];
+        self.pos = (self.pos + 1) % len;
+        sample
+    }
+
+    /// Fill the delay line with silence.
+    pub fn reset(&mut self) {
+        self.buffer.iter_mut().for_each(|x| *x = T::default());
+    }
+}
@@ -0,0 +1,145 @@
+use std::f64::consts::{FRAC_1_SQRT_2, PI, TAU};
+
+use crate::{flush_denormal, Float};
+
+/// A second order IIR filter, using the cookbook formulas by Robert
+/// Bristow-Johnson. The default filter passes everything through unchanged.
+/// The coefficients are always designed at double precision, and then rounded
+/// to the sample type.
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad<T = f32> {
+    b0: T,
+    b1: T,
+    b2: T,
+    a1: T,
+    a2: T,
+    // The filter state, in transposed direct form II.
+    z1: T,
+    z2: T,
+}
+
+impl<T: Float> Default for Biquad<T> {
+    fn default() -> Self {
+        Biquad {
+            b0: T::from_f64(1.0),
+            b1: T::default(),
+            b2: T::default(),
+            a1: T::default(),
+            a2: T::default(),
+            z1: T::default(),
+            z2: T::default(),
+        }
+    }
+}
+
+impl<T: Float> Biquad<T> {
+    /// Turn the filter into a lowpass filter. The filter state is kept, so this
+    /// may be called while processing.
+    pub fn set_lowpass(&mut self, cutoff: f32, q: f32, sample_rate: f32) {
+        let (cos, alpha) = Self::intermediates(cutoff, q as f64, sample_rate);
+        self.set_coefficients(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        );
+    }
+
+    /// Turn the filter into a highpass filter. The filter state is kept, so
+    /// this may be called while processing.
+    pub fn set_highpass(&mut self, cutoff: f32, q: f32, sample_rate: f32) {
+        let (cos, alpha) = Self::intermediates(cutoff, q as f64, sample_rate);
+        self.set_coefficients(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        );
+    }
+
+    /// Turn the filter into a high shelf, which amplifies frequencies above
+    /// `cutoff` by `gain` decibels. Shelves with opposite gains undo each
+    /// other. The filter state is kept, so this may be called while processing.
+    pub fn set_high_shelf(&mut self, cutoff: f32, gain: f32, sample_rate: f32) {
+        let a = 10.0f64.powf(gain as f64 / 40.0);
+        let (cos, alpha) = Self::intermediates(cutoff, FRAC_1_SQRT_2, sample_rate);
+        let beta = 2.0 * a.sqrt() * alpha;
+        self.set_coefficients(
+            a * ((a + 1.0) + (a - 1.0) * cos + beta),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - beta),
+            (a + 1.0) - (a - 1.0) * cos + beta,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - beta,
+        );
+    }
+
+    pub fn next(&mut self, input: T) -> T {
+        let out = self.b0 * input + self.z1;
+        self.z1 = flush_denormal(self.b1 * input - self.a1 * out + self.z2);
+        self.z2 = flush_denormal(self.b2 * input - self.a2 * out);
+        out
+    }
+
+    /// Clear the filter state.
+    pub fn reset(&mut self) {
+        self.z1 = T::default();
+        self.z2 = T::default();
+    }
+
+    fn intermediates(cutoff: f32, q: f64, sample_rate: f32) -> (f64, f64) {
+        let sample_rate = sample_rate as f64;
+        // Keep the cutoff below Nyquist, where the formulas break down.
+        let cutoff = (cutoff as f64).clamp(1.0, sample_rate * 0.49);
+        let omega = 2.0 * PI * cutoff / sample_rate;
+        (omega.cos(), omega.sin() / (2.0 * q))
+    }
+
+    fn set_coefficients(&mut self, b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) {
+        self.b0 = T::from_f64(b0 / a0);
+        self.b1 = T::from_f64(b1 / a0);
+        self.b2 = T::from_f64(b2 / a0);
+        self.a1 = T::from_f64(a1 / a0);
+        self.a2 = T::from_f64(a2 / a0);
+    }
+}
+
+/// A one-pole highpass filter which removes DC offset.
+#[derive(Debug, Clone, Copy)]
+pub struct DcBlocker<T = f32> {
+    coefficient: T,
+    last_input: T,
+    last_output: T,
+}
+
+impl<T: Float> Default for DcBlocker<T> {
+    fn default() -> Self {
+        DcBlocker {
+            coefficient: T::from_f64(1.0),
+            last_input: T::default(),
+            last_output: T::default(),
+        }
+    }
+}
+
+impl<T: Float> DcBlocker<T> {
+    pub fn set_cutoff(&mut self, cutoff: f32, sample_rate: f32) {
+        self.coefficient = T::from_f64((-TAU * cutoff as f64 / sample_rate as f64).exp());
+    }
+
+    pub fn next(&mut self, input: T) -> T {
+        let output = flush_denormal(input - self.last_input + self.coefficient * self.last_output);
+        self.last_input = input;
+        self.last_output = output;
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.last_input = T::default();
+        self.last_output = T::default();
+    }
+}
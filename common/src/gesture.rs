@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use vst::{host::Host, plugin::HostCallback, util::AtomicFloat};
+
+use crate::range::Range;
+
+/// A change to one parameter made from the plugin's own GUI, such as dragging
+/// a knob. The host is told when the gesture begins and, once this is dropped,
+/// that it has ended, so that automation recording captures the whole drag as
+/// one edit instead of many separate ones.
+pub struct Gesture<'a> {
+    host: &'a HostCallback,
+    value: &'a AtomicFloat,
+    // The generation of the parameters the value belongs to
+    generation: &'a AtomicUsize,
+    range: Range,
+    index: i32,
+}
+
+impl<'a> Gesture<'a> {
+    pub fn begin(
+        host: &'a HostCallback,
+        value: &'a AtomicFloat,
+        generation: &'a AtomicUsize,
+        range: Range,
+        index: i32,
+    ) -> Gesture<'a> {
+        host.begin_edit(index);
+        Gesture {
+            host,
+            value,
+            generation,
+            range,
+            index,
+        }
+    }
+
+    /// Set the parameter's normalized value and report it to the host.
+    /// Stepped parameters are snapped to their nearest step.
+    pub fn set(&self, value: f32) {
+        let value = self.range.snap(value);
+        self.value.set(value);
+        self.generation.fetch_add(1, Ordering::Release);
+        self.host.automate(self.index, value);
+    }
+}
+
+impl Drop for Gesture<'_> {
+    fn drop(&mut self) {
+        self.host.end_edit(self.index);
+    }
+}
+
+/// Parameters changed on the audio thread, such as by Learn, which the host
+/// hasn't been told about yet. The audio thread mustn't call back into the
+/// host, so the changes are reported the next time the host calls in from its
+/// own thread, such as to read a parameter.
+pub struct PendingEdits {
+    pending: Vec<AtomicBool>,
+}
+
+impl PendingEdits {
+    pub fn new(count: usize) -> PendingEdits {
+        PendingEdits {
+            pending: (0..count).map(|_| AtomicBool::new(false)).collect(),
+        }
+    }
+
+    /// Note that the parameter at `index` has changed. This doesn't call the
+    /// host, so it is safe to call from the audio thread.
+    pub fn push(&self, index: i32) {
+        if let Some(pending) = self.pending.get(index as usize) {
+            pending.store(true, Ordering::Release);
+        }
+    }
+
+    /// Report each changed parameter to the host as a gesture of its own.
+    /// `value` returns the current normalized value of a parameter from its
+    /// index.
+    pub fn publish(&self, host: &HostCallback, value: impl Fn(i32) -> f32) {
+        for (index, pending) in self.pending.iter().enumerate() {
+            if pending.swap(false, Ordering::AcqRel) {
+                let index = index as i32;
+                host.begin_edit(index);
+                host.automate(index, value(index));
+                host.end_edit(index);
+            }
+        }
+    }
+}
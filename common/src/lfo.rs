@@ -0,0 +1,138 @@
+use std::f32::consts::TAU;
+
+use crate::{rng::Rng, transport::Transport};
+
+/// The waveform of an LFO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    SawUp,
+    SawDown,
+    Square,
+    /// A new random value each cycle, held until the next (sample and hold).
+    Random,
+}
+
+impl LfoShape {
+    /// Returns the value of the LFO, from 0.0 to 1.0, `phase` of the way
+    /// through a cycle. `random` is the value of the Random shape this cycle.
+    pub fn value(&self, phase: f32, random: f32) -> f32 {
+        match self {
+            LfoShape::Sine => 0.5 - 0.5 * (phase * TAU).cos(),
+            LfoShape::Triangle => 1.0 - (2.0 * phase - 1.0).abs(),
+            LfoShape::SawUp => phase,
+            LfoShape::SawDown => 1.0 - phase,
+            LfoShape::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            LfoShape::Random => random,
+        }
+    }
+}
+
+impl From<f32> for LfoShape {
+    fn from(x: f32) -> Self {
+        match (x * 6.0) as usize {
+            0 => LfoShape::Sine,
+            1 => LfoShape::Triangle,
+            2 => LfoShape::SawUp,
+            3 => LfoShape::SawDown,
+            4 => LfoShape::Square,
+            _ => LfoShape::Random,
+        }
+    }
+}
+
+impl std::fmt::Display for LfoShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LfoShape::Sine => write!(f, "Sine"),
+            LfoShape::Triangle => write!(f, "Triangle"),
+            LfoShape::SawUp => write!(f, "Saw Up"),
+            LfoShape::SawDown => write!(f, "Saw Down"),
+            LfoShape::Square => write!(f, "Square"),
+            LfoShape::Random => write!(f, "Random"),
+        }
+    }
+}
+
+/// How fast an LFO cycles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoRate {
+    /// Free-running, in cycles per second
+    Hz(f32),
+    /// Following the host's tempo, with each cycle this many quarter notes long
+    Beats(f64),
+}
+
+/// A low frequency oscillator, for modulating parameters. Synced LFOs follow
+/// the host's position while its transport runs, and otherwise keep counting
+/// from wherever they were.
+#[derive(Debug, Clone)]
+pub struct Lfo {
+    // The position, in cycles. The whole part counts the cycles.
+    phase: f64,
+    // The cycle that `random` was drawn for, or None if the LFO was just reset
+    cycle: Option<i64>,
+    random: f32,
+    new_cycle: bool,
+    rng: Rng,
+}
+
+impl Lfo {
+    /// `seed` seeds the values of the Random shape.
+    pub fn new(seed: u32) -> Lfo {
+        Lfo {
+            phase: 0.0,
+            cycle: None,
+            random: 0.0,
+            new_cycle: false,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Start again from the beginning of a cycle.
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.cycle = None;
+    }
+
+    /// Line a synced LFO up with the host's `position`, in quarter notes.
+    /// This should be called at the start of each block while the host's
+    /// transport runs, such as with `Transport::playing_position`. Free-running
+    /// LFOs are left alone.
+    pub fn sync(&mut self, rate: LfoRate, position: f64) {
+        if let LfoRate::Beats(beats) = rate {
+            self.phase = position / beats;
+        }
+    }
+
+    /// Return the value of the LFO, from 0.0 to 1.0, then move on by one
+    /// sample.
+    pub fn advance(&mut self, shape: LfoShape, rate: LfoRate, transport: &Transport) -> f32 {
+        let cycle = self.phase.floor() as i64;
+        self.new_cycle = self.cycle != Some(cycle);
+        if self.new_cycle {
+            self.cycle = Some(cycle);
+            self.random = self.rng.next_f32();
+        }
+        let value = shape.value((self.phase - cycle as f64) as f32, self.random);
+
+        self.phase += match rate {
+            LfoRate::Hz(hz) => hz as f64 / transport.sample_rate as f64,
+            LfoRate::Beats(beats) => transport.quarters_per_sample() / beats,
+        };
+        value
+    }
+
+    /// True if the value last returned by `advance` was the first of a new
+    /// cycle.
+    pub fn new_cycle(&self) -> bool {
+        self.new_cycle
+    }
+}
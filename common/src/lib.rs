@@ -1,9 +1,112 @@
+pub mod chunk;
+pub mod delay;
+pub mod filter;
+pub mod gesture;
+pub mod lfo;
 pub mod macros;
+pub mod midi;
+pub mod oversample;
+pub mod range;
+pub mod ring_buffer;
+pub mod rng;
+pub mod smooth;
+pub mod transport;
+
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+
+pub use common_derive::VstParameters;
+
+/// A built-in program which the host can select from its preset list.
+pub struct Preset<T: 'static> {
+    pub name: &'static str,
+    /// The normalized values to set. Parameters not listed here are reset to
+    /// their defaults.
+    pub values: &'static [(T, f32)],
+}
+
+/// Parameters which the host may automate. Parameters which trigger one-off
+/// actions, rather than describing the plugin's state, should return false.
+pub trait Automatable {
+    fn can_be_automated(&self) -> bool {
+        true
+    }
+}
+
+/// A sample type, so that the filters can run at single or double precision.
+pub trait Float:
+    Copy
+    + Default
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + Sum
+{
+    fn from_f64(x: f64) -> Self;
+    fn abs(self) -> Self;
+}
+
+impl Float for f32 {
+    fn from_f64(x: f64) -> Self {
+        x as f32
+    }
+
+    fn abs(self) -> Self {
+        f32::abs(self)
+    }
+}
+
+impl Float for f64 {
+    fn from_f64(x: f64) -> Self {
+        x
+    }
+
+    fn abs(self) -> Self {
+        f64::abs(self)
+    }
+}
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
 
 pub fn make_strings(value: f32, label: &str) -> (String, String) {
     (format!("{:.2}", value), label.to_string())
 }
 
+/// The `initial_delay` a plugin's `Info` reports for a latency of `samples`.
+/// Note that most hosts only read this when the plugin is loaded or resumed,
+/// so a parameter which changes the latency mid-playback won't be compensated
+/// until then.
+pub fn initial_delay(samples: usize) -> i32 {
+    samples as i32
+}
+
+/// Below this, `flush_denormal` flushes values to zero. This is far too quiet
+/// to hear, but still well clear of the denormal range.
+const DENORMAL_THRESHOLD: f64 = 1e-20;
+
+/// Flush `x` to zero if it is too small to matter. State which decays towards
+/// zero, such as a filter's once its input goes silent, otherwise ends up as
+/// denormal floats, which many CPUs are very slow to process.
+pub fn flush_denormal<T: Float>(x: T) -> T {
+    if x.abs() < T::from_f64(DENORMAL_THRESHOLD) {
+        T::default()
+    } else {
+        x
+    }
+}
+
 pub fn ease_in_expo(x: f32) -> f32 {
     if x <= 0.0 {
         0.0
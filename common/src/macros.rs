@@ -9,10 +9,48 @@
 ///     returns a tuple where the first String is the parameter's name
 ///     (ex: "Master Volume") and the second tuple is the parameter's value
 ///     (ex: "12 db")
+/// `$parameter_type` must also implement `Automatable`.
+/// `$presets` is an optional `&[Preset<$parameter_type>]` listing the plugin's
+/// built-in programs.
 #[macro_export]
 macro_rules! impl_plugin_parameters {
     ($raw_parameters: ident, $parameter_type: ident) => {
+        impl_plugin_parameters! {$raw_parameters, $parameter_type, &[]}
+    };
+    ($raw_parameters: ident, $parameter_type: ident, $presets: expr) => {
         impl vst::plugin::PluginParameters for $raw_parameters {
+            fn change_preset(&self, preset: i32) {
+                use std::convert::TryFrom;
+                let presets: &[$crate::Preset<$parameter_type>] = $presets;
+                if let Some(preset_values) = presets.get(preset as usize) {
+                    let mut index = 0;
+                    while let Ok(parameter) = $parameter_type::try_from(index) {
+                        self.get_ref(parameter)
+                            .set($raw_parameters::get_default(parameter));
+                        index += 1;
+                    }
+                    for &(parameter, value) in preset_values.values {
+                        self.get_ref(parameter).set(value);
+                    }
+                    self.mark_changed();
+                    self.current_preset
+                        .store(preset, std::sync::atomic::Ordering::Relaxed);
+                }
+            }
+
+            fn get_preset_num(&self) -> i32 {
+                self.current_preset
+                    .load(std::sync::atomic::Ordering::Relaxed)
+            }
+
+            fn get_preset_name(&self, preset: i32) -> String {
+                let presets: &[$crate::Preset<$parameter_type>] = $presets;
+                match presets.get(preset as usize) {
+                    Some(preset) => preset.name.to_string(),
+                    None => "".to_string(),
+                }
+            }
+
             fn get_parameter_label(&self, index: i32) -> String {
                 use std::convert::TryFrom;
                 if let Ok(parameter) = $parameter_type::try_from(index) {
@@ -24,6 +62,7 @@ macro_rules! impl_plugin_parameters {
 
             fn get_parameter_text(&self, index: i32) -> String {
                 use std::convert::TryFrom;
+                self.publish_pending();
                 if let Ok(parameter) = $parameter_type::try_from(index) {
                     self.get_strings(parameter).0
                 } else {
@@ -40,8 +79,12 @@ macro_rules! impl_plugin_parameters {
                 }
             }
 
+            // Hosts read the parameters from their own thread every so
+            // often, which makes this a good time to report changes made on
+            // the audio thread.
             fn get_parameter(&self, index: i32) -> f32 {
                 use std::convert::TryFrom;
+                self.publish_pending();
                 if let Ok(parameter) = $parameter_type::try_from(index) {
                     self.get(parameter)
                 } else {
@@ -52,6 +95,7 @@ macro_rules! impl_plugin_parameters {
             fn set_parameter(&self, index: i32, value: f32) {
                 use std::convert::TryFrom;
                 if let Ok(parameter) = $parameter_type::try_from(index) {
+                    let value = parameter.range().snap(value);
                     // This is needed because some VST hosts, such as Ableton, echo a
                     // parameter change back to the plugin. This causes issues such as
                     // weird knob behavior where the knob "flickers" because the user tries
@@ -68,74 +112,46 @@ macro_rules! impl_plugin_parameters {
 
             fn can_be_automated(&self, index: i32) -> bool {
                 use std::convert::TryFrom;
-                $parameter_type::try_from(index).is_ok()
-            }
-
-            fn string_to_parameter(&self, _index: i32, _text: String) -> bool {
-                false
+                $parameter_type::try_from(index)
+                    .is_ok_and(|parameter| $crate::Automatable::can_be_automated(&parameter))
             }
-        }
-    };
-}
 
-#[macro_export]
-macro_rules! impl_get_set {
-    ($raw_parameters: ident, $parameter_type: ident) => {
-        impl $raw_parameters {
-            pub fn set(&self, value: f32, parameter: $parameter_type) {
-                // These are needed so Ableton will notice parameter changes in the
-                // "Configure" window.
-                // TODO: investigate if I should send this only on mouseup/mousedown
-                self.host.begin_edit(parameter.into());
-                self.get_ref(parameter).set(value);
-                self.host.end_edit(parameter.into());
+            fn get_preset_data(&self) -> Vec<u8> {
+                self.publish_pending();
+                self.to_chunk().to_bytes()
             }
 
-            pub fn get(&self, parameter: $parameter_type) -> f32 {
-                self.get_ref(parameter).get()
+            // There is only ever one set of values, so a bank is the same as
+            // a preset.
+            fn get_bank_data(&self) -> Vec<u8> {
+                self.publish_pending();
+                self.to_chunk().to_bytes()
             }
-        }
-    };
-}
 
-#[macro_export]
-macro_rules! impl_display {
-    ($raw_parameters: ident, $parameter_type: ident;
-     $($variant:ident, $field_name:ident, $name:expr, $idx:expr, $default:expr, $string:expr;)*) => {
-        impl std::fmt::Display for $parameter_type {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                match self {
-                    $($parameter_type::$variant => write!(f, $name),)*
+            fn load_preset_data(&self, data: &[u8]) {
+                if let Some(chunk) = $crate::chunk::Chunk::from_bytes(data) {
+                    self.load_chunk(&chunk);
                 }
             }
-        }
-    };
-}
 
-#[macro_export]
-macro_rules! impl_from_i32 {
-    ($raw_parameters: ident, $parameter_type: ident;
-     $($variant:ident, $field_name:ident, $name:expr, $idx:expr, $default:expr, $string:expr;)*) => {
-        impl std::convert::TryFrom<i32> for $parameter_type {
-            type Error = ();
-            fn try_from(x: i32) -> Result<Self, Self::Error> {
-                match x {
-                    $($idx => Ok($parameter_type::$variant),)*
-                    _ => Err(()),
+            fn load_bank_data(&self, data: &[u8]) {
+                if let Some(chunk) = $crate::chunk::Chunk::from_bytes(data) {
+                    self.load_chunk(&chunk);
                 }
             }
-        }
-    }
-}
 
-#[macro_export]
-macro_rules! impl_into_i32 {
-    ($raw_parameters: ident, $parameter_type: ident;
-     $($variant:ident, $field_name:ident, $name:expr, $idx:expr, $default:expr, $string:expr;)*) => {
-        impl std::convert::From<$parameter_type> for i32 {
-            fn from(x: $parameter_type) -> i32 {
-                match x {
-                    $($parameter_type::$variant => $idx,)*
+            fn string_to_parameter(&self, index: i32, text: String) -> bool {
+                use std::convert::TryFrom;
+                let parameter = match $parameter_type::try_from(index) {
+                    Ok(parameter) => parameter,
+                    Err(()) => return false,
+                };
+                match parameter.range().parse(&text) {
+                    Some(value) => {
+                        self.set(value, parameter);
+                        true
+                    }
+                    None => false,
                 }
             }
         }
@@ -143,104 +159,83 @@ macro_rules! impl_into_i32 {
 }
 
 #[macro_export]
-macro_rules! impl_get_ref {
-    ($raw_parameters: ident, $parameter_type: ident;
-     $($variant:ident, $field_name:ident, $name:expr, $idx:expr, $default:expr, $string:expr;)*) => {
+macro_rules! impl_get_set {
+    ($raw_parameters: ident, $parameter_type: ident) => {
         impl $raw_parameters {
-            fn get_ref(&self, x: $parameter_type) -> &vst::util::AtomicFloat {
-                match x {
-                    $($parameter_type::$variant => &self.$field_name,)*
-                }
+            /// Set `parameter` as a gesture of its own. This suits one-off
+            /// changes, such as from Learn. Changes which span several calls,
+            /// such as dragging a knob, should use `begin_gesture` instead.
+            pub fn set(&self, value: f32, parameter: $parameter_type) {
+                // These are needed so Ableton will notice parameter changes in the
+                // "Configure" window.
+                let value = parameter.range().snap(value);
+                self.host.begin_edit(parameter.into());
+                self.get_ref(parameter).set(value);
+                self.mark_changed();
+                self.host.end_edit(parameter.into());
             }
-        }
-    };
-}
 
-#[macro_export]
-macro_rules! impl_get_default {
-    ($raw_parameters: ident, $parameter_type: ident;
-     $($variant:ident, $field_name:ident, $name:expr, $idx:expr, $default:expr, $string:expr;)*) => {
-        impl $raw_parameters {
-            fn get_default(x: $parameter_type) -> f32 {
-                match x {
-                    $($parameter_type::$variant => $default,)*
-                }
+            /// Set `parameter` from the audio thread, such as when a Learn
+            /// pass finishes. The value takes effect immediately, but the host
+            /// is only told about it once `publish_pending` is called from
+            /// the host's side.
+            pub fn set_from_audio(&self, value: f32, parameter: $parameter_type) {
+                self.set_silently(value, parameter);
+                self.pending.push(parameter.into());
             }
-        }
-    };
-}
 
-#[macro_export]
-macro_rules! impl_default {
-    ($raw_parameters: ident, $parameter_type: ident;
-     $($variant:ident, $field_name:ident, $name:expr, $idx:expr, $default:expr, $string:expr;)*) => {
-        impl $raw_parameters {
-            fn default(host: vst::plugin::HostCallback) -> Self {
-                $raw_parameters {
-                    $($field_name: vst::util::AtomicFloat::new($default),)*
-                    host,
-                }
+            /// Set `parameter` without ever telling the host, for values
+            /// driven by something other than the host, such as a MIDI CC,
+            /// which shouldn't be recorded as automation. This is safe to call
+            /// from the audio thread.
+            pub fn set_silently(&self, value: f32, parameter: $parameter_type) {
+                let value = parameter.range().snap(value);
+                self.get_ref(parameter).set(value);
+                self.mark_changed();
             }
-        }
-    };
-}
 
-#[macro_export]
-macro_rules! impl_get_strings {
-    ($raw_parameters: ident, $parameter_type: ident;
-     $($variant:ident, $field_name:ident, $name:expr, $idx:expr, $default:expr, $string:expr;)*) => {
-        impl $raw_parameters {
-            /// Returns a user-facing text output for the given parameter. This is broken
-            /// into a tuple consisting of (`value`, `units`)
-            fn get_strings(&self, parameter: $parameter_type) -> (String, String) {
-                let params = Parameters::from(self);
-                match parameter {
-                    $($parameter_type::$variant => $string(params.$field_name),)*
-                }
+            /// Report the changes made by `set_from_audio` to the host.
+            pub fn publish_pending(&self) {
+                use std::convert::TryFrom;
+                self.pending.publish(&self.host, |index| {
+                    $parameter_type::try_from(index).map_or(0.0, |parameter| self.get(parameter))
+                });
+            }
+
+            /// Start changing `parameter` from the plugin's own GUI, such as
+            /// when a knob is pressed. The gesture ends when the returned
+            /// value is dropped.
+            pub fn begin_gesture(
+                &self,
+                parameter: $parameter_type,
+            ) -> $crate::gesture::Gesture<'_> {
+                $crate::gesture::Gesture::begin(
+                    &self.host,
+                    self.get_ref(parameter),
+                    &self.generation,
+                    parameter.range(),
+                    parameter.into(),
+                )
+            }
+
+            /// A count of the changes made to the parameters, which wraps
+            /// around. If this hasn't moved since `Parameters` were built from
+            /// these, they are still current.
+            pub fn generation(&self) -> usize {
+                self.generation.load(std::sync::atomic::Ordering::Acquire)
+            }
+
+            /// Bump the generation, for values which are changed without
+            /// going through `set`, such as the sample rate. This must be
+            /// called after the values are changed.
+            pub fn mark_changed(&self) {
+                self.generation
+                    .fetch_add(1, std::sync::atomic::Ordering::Release);
             }
-        }
-    };
-}
 
-#[macro_export]
-macro_rules! generate_raw_params {
-    ($raw_parameters: ident, $parameter_type: ident;
-     $($variant:ident, $field_name:ident, $name:expr, $idx:expr, $default:expr, $string:expr;)*) => {
-        /// The raw parameter values that a host DAW will set and modify.
-        /// These are unscaled and are always in the [0.0, 1.0] range
-        pub struct $raw_parameters {
-            $($field_name: AtomicFloat,)*
-            /// The host callback, used for communicating with the VST host
-            pub host: vst::plugin::HostCallback,
-        }
-    };
-}
-
-#[macro_export]
-macro_rules! generate_parameter_type {
-    ($raw_parameters: ident, $parameter_type: ident;
-     $($variant:ident, $field_name:ident, $name:expr, $idx:expr, $default:expr, $string:expr;)*) => {
-        /// The list of parameters that exist.
-        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-        pub enum $parameter_type {
-            $($variant,)*
+            pub fn get(&self, parameter: $parameter_type) -> f32 {
+                self.get_ref(parameter).get()
+            }
         }
     };
 }
-
-#[macro_export]
-macro_rules! impl_all {
-    ($raw_parameters: ident, $parameter_type: ident, $table: ident) => {
-        impl_plugin_parameters! {$raw_parameters, $parameter_type}
-        impl_get_set! {$raw_parameters, $parameter_type}
-        $table! {generate_raw_params}
-        $table! {generate_parameter_type}
-        $table! {impl_from_i32}
-        $table! {impl_into_i32}
-        $table! {impl_display}
-        $table! {impl_get_ref}
-        $table! {impl_default}
-        $table! {impl_get_default}
-        $table! {impl_get_strings}
-    };
-}
@@ -0,0 +1,216 @@
+use vst::{api::Events, event::Event};
+
+/// A MIDI message, parsed from the raw bytes of a VST MIDI event. Channels
+/// count from 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    /// A note off. A note on with zero velocity is parsed as one of these.
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// `value` goes from -1.0 to just under 1.0, with 0.0 in the center.
+    PitchBend {
+        channel: u8,
+        value: f32,
+    },
+}
+
+impl MidiMessage {
+    /// Parse the bytes of a MIDI event, or return None for kinds of messages
+    /// which aren't handled here, such as aftertouch.
+    pub fn parse(data: [u8; 3]) -> Option<MidiMessage> {
+        let [status, data1, data2] = data;
+        let channel = status & 0x0F;
+        match status & 0xF0 {
+            0x90 if data2 > 0 => Some(MidiMessage::NoteOn {
+                channel,
+                note: data1,
+                velocity: data2,
+            }),
+            0x80 | 0x90 => Some(MidiMessage::NoteOff {
+                channel,
+                note: data1,
+                velocity: data2,
+            }),
+            0xB0 => Some(MidiMessage::ControlChange {
+                channel,
+                controller: data1,
+                value: data2,
+            }),
+            0xE0 => {
+                let value = ((data2 as i32) << 7 | data1 as i32) - 0x2000;
+                Some(MidiMessage::PitchBend {
+                    channel,
+                    value: value as f32 / 0x2000 as f32,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        match *self {
+            MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::PitchBend { channel, .. } => channel,
+        }
+    }
+}
+
+/// A MIDI message, along with when it happens in the block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedMessage {
+    /// The number of samples from the start of the block
+    pub offset: usize,
+    pub message: MidiMessage,
+}
+
+/// Parses the MIDI events the host sends, remembering the last status byte so
+/// that messages which leave it out, using running status, can be read.
+#[derive(Debug, Clone, Default)]
+pub struct MidiParser {
+    running_status: Option<u8>,
+}
+
+impl MidiParser {
+    /// Parse the bytes of a MIDI event, which may use running status. Returns
+    /// None for messages which aren't handled, like `MidiMessage::parse`.
+    pub fn parse(&mut self, data: [u8; 3]) -> Option<MidiMessage> {
+        let [status, data1, data2] = data;
+        match status {
+            // A data byte, so the status is the last one seen.
+            0x00..=0x7F => MidiMessage::parse([self.running_status?, status, data1]),
+            0x80..=0xEF => {
+                self.running_status = Some(status);
+                MidiMessage::parse([status, data1, data2])
+            }
+            // System common messages cancel running status, but real-time
+            // messages can appear anywhere without affecting it.
+            0xF0..=0xF7 => {
+                self.running_status = None;
+                None
+            }
+            0xF8..=0xFF => None,
+        }
+    }
+
+    /// The MIDI messages in `events`, in the order the host sent them. SysEx
+    /// and unhandled messages are skipped.
+    pub fn messages<'a>(
+        &'a mut self,
+        events: &'a Events,
+    ) -> impl Iterator<Item = TimedMessage> + 'a {
+        events.events().filter_map(move |event| match event {
+            Event::Midi(event) => Some(TimedMessage {
+                offset: event.delta_frames.max(0) as usize,
+                message: self.parse(event.data)?,
+            }),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_notes() {
+        assert_eq!(
+            MidiMessage::parse([0x91, 60, 100]),
+            Some(MidiMessage::NoteOn {
+                channel: 1,
+                note: 60,
+                velocity: 100
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse([0x82, 60, 40]),
+            Some(MidiMessage::NoteOff {
+                channel: 2,
+                note: 60,
+                velocity: 40
+            })
+        );
+    }
+
+    #[test]
+    fn note_on_without_velocity_is_note_off() {
+        assert_eq!(
+            MidiMessage::parse([0x90, 64, 0]),
+            Some(MidiMessage::NoteOff {
+                channel: 0,
+                note: 64,
+                velocity: 0
+            })
+        );
+    }
+
+    #[test]
+    fn parses_control_change() {
+        let message = MidiMessage::parse([0xBF, 7, 127]);
+        assert_eq!(
+            message,
+            Some(MidiMessage::ControlChange {
+                channel: 15,
+                controller: 7,
+                value: 127
+            })
+        );
+        assert_eq!(message.map(|message| message.channel()), Some(15));
+    }
+
+    #[test]
+    fn parses_pitch_bend() {
+        let bend = |data1, data2| match MidiMessage::parse([0xE0, data1, data2]) {
+            Some(MidiMessage::PitchBend { value, .. }) => value,
+            message => panic!("expected a pitch bend, got {:?}", message),
+        };
+        assert_eq!(bend(0x00, 0x40), 0.0);
+        assert_eq!(bend(0x00, 0x00), -1.0);
+        assert!(bend(0x7F, 0x7F) < 1.0);
+    }
+
+    #[test]
+    fn skips_unhandled_messages() {
+        // Polyphonic aftertouch and a timing clock
+        assert_eq!(MidiMessage::parse([0xA0, 60, 10]), None);
+        assert_eq!(MidiMessage::parse([0xF8, 0, 0]), None);
+    }
+
+    #[test]
+    fn running_status() {
+        let mut parser = MidiParser::default();
+        // Nothing to run from yet
+        assert_eq!(parser.parse([60, 100, 0]), None);
+
+        parser.parse([0x93, 60, 100]);
+        let note_on = |note| {
+            Some(MidiMessage::NoteOn {
+                channel: 3,
+                note,
+                velocity: 100,
+            })
+        };
+        assert_eq!(parser.parse([62, 100, 0]), note_on(62));
+        // Real-time messages don't interrupt running status...
+        assert_eq!(parser.parse([0xF8, 0, 0]), None);
+        assert_eq!(parser.parse([64, 100, 0]), note_on(64));
+        // ...but system common messages do.
+        assert_eq!(parser.parse([0xF2, 0, 0]), None);
+        assert_eq!(parser.parse([65, 100, 0]), None);
+    }
+}
@@ -0,0 +1,268 @@
+use std::f64::consts::PI;
+
+use crate::Float;
+
+/// The largest oversampling factor.
+pub const MAX_FACTOR: usize = 8;
+/// The number of 2x stages needed for `MAX_FACTOR`.
+const MAX_STAGES: usize = 3;
+/// The length of the shortest and longest halfband filters.
+const MIN_TAPS: usize = 9;
+const MAX_TAPS: usize = 65;
+
+/// Runs a nonlinear function at a multiple of the sample rate, so that the
+/// harmonics it creates above the host's Nyquist frequency are filtered out
+/// instead of aliasing. Each 2x stage uses a pair of halfband filters.
+pub struct Oversampler<T = f32> {
+    stages: Vec<Stage<T>>,
+    // The number of stages in use, where the oversampling factor is
+    // 2^stage_count.
+    stage_count: usize,
+    taps: usize,
+}
+
+impl<T: Float> Default for Oversampler<T> {
+    /// An oversampler which doesn't oversample until `set_params` is called.
+    fn default() -> Self {
+        Oversampler {
+            stages: (0..MAX_STAGES).map(|_| Stage::new()).collect(),
+            stage_count: 0,
+            taps: 0,
+        }
+    }
+}
+
+impl<T: Float> Oversampler<T> {
+    /// Set the oversampling factor, which must be a power of two no larger
+    /// than `MAX_FACTOR`, and the length of each halfband filter, which is
+    /// rounded to the nearest valid length. Longer filters remove more
+    /// aliasing, but add more latency. Changing either clears the filters.
+    pub fn set_params(&mut self, factor: usize, taps: usize) {
+        debug_assert!(factor.is_power_of_two() && factor <= MAX_FACTOR);
+        let stage_count = factor.min(MAX_FACTOR).trailing_zeros() as usize;
+        let taps = valid_taps(taps);
+        if stage_count == self.stage_count && taps == self.taps {
+            return;
+        }
+        self.stage_count = stage_count;
+        self.taps = taps;
+        for stage in &mut self.stages {
+            stage.up.set_taps(taps);
+            stage.down.set_taps(taps);
+        }
+    }
+
+    /// The delay, in samples at the host rate, added by the filters.
+    pub fn latency(&self) -> usize {
+        latency(1 << self.stage_count, self.taps)
+    }
+
+    /// Return the next sample of `input` with `f` applied at the oversampled
+    /// rate.
+    pub fn process(&mut self, input: T, mut f: impl FnMut(T) -> T) -> T {
+        let stages = &mut self.stages[..self.stage_count];
+        let mut samples = [T::default(); MAX_FACTOR];
+        samples[0] = input;
+        let mut len = 1;
+        for stage in stages.iter_mut() {
+            let low = samples;
+            for i in 0..len {
+                let (even, odd) = stage.up.upsample(low[i]);
+                samples[2 * i] = even;
+                samples[2 * i + 1] = odd;
+            }
+            len *= 2;
+        }
+        for sample in &mut samples[..len] {
+            *sample = f(*sample);
+        }
+        for stage in stages.iter_mut().rev() {
+            len /= 2;
+            for i in 0..len {
+                samples[i] = stage.down.downsample(samples[2 * i], samples[2 * i + 1]);
+            }
+        }
+        samples[0]
+    }
+
+    /// Apply `f` to `block` at the oversampled rate, replacing each sample with
+    /// the result.
+    pub fn process_oversampled(&mut self, block: &mut [T], mut f: impl FnMut(T) -> T) {
+        for sample in block {
+            *sample = self.process(*sample, &mut f);
+        }
+    }
+}
+
+/// The delay, in samples at the host rate, added by oversampling by `factor`
+/// with filters `taps` long, after rounding it as `set_params` does.
+pub fn latency(factor: usize, taps: usize) -> usize {
+    if factor <= 1 {
+        return 0;
+    }
+    let taps = valid_taps(taps);
+    // Each filter delays by half its length at its own rate, and each stage
+    // runs at twice the rate of the last.
+    let center = (taps - 1) / 2;
+    2 * center * (factor - 1) / factor
+}
+
+/// The nearest filter length from `MIN_TAPS` to `MAX_TAPS` which is one more
+/// than a multiple of 8. The center tap then lands on a whole sample at every
+/// stage, which keeps the latency a whole number of samples.
+fn valid_taps(taps: usize) -> usize {
+    (taps.clamp(MIN_TAPS, MAX_TAPS) + 3) / 8 * 8 + 1
+}
+
+/// A 2x upsampler and downsampler pair.
+struct Stage<T> {
+    up: Halfband<T>,
+    down: Halfband<T>,
+}
+
+impl<T: Float> Stage<T> {
+    fn new() -> Stage<T> {
+        Stage {
+            up: Halfband::new(),
+            down: Halfband::new(),
+        }
+    }
+}
+
+/// A windowed-sinc lowpass at a quarter of the sample rate. Every other tap,
+/// apart from the center, is zero.
+struct Halfband<T> {
+    taps: Vec<T>,
+    // The most recent input samples, newest first.
+    history: Vec<T>,
+}
+
+impl<T: Float> Halfband<T> {
+    fn new() -> Halfband<T> {
+        Halfband {
+            taps: Vec::with_capacity(MAX_TAPS),
+            history: Vec::with_capacity(MAX_TAPS),
+        }
+    }
+
+    /// Design a filter with `len` taps and clear the history. This doesn't
+    /// allocate.
+    fn set_taps(&mut self, len: usize) {
+        let center = (len - 1) as f64 / 2.0;
+        let taps = (0..len).map(|n| {
+            let x = (n as f64 - center) / 2.0;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            // Blackman window
+            let phase = 2.0 * PI * n as f64 / (len - 1) as f64;
+            let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+            sinc * window
+        });
+        let sum: f64 = taps.clone().sum();
+        self.taps.clear();
+        self.taps.extend(taps.map(|tap| T::from_f64(tap / sum)));
+        self.history.clear();
+        self.history.resize(len, T::default());
+    }
+
+    fn push(&mut self, x: T) {
+        self.history.rotate_right(1);
+        self.history[0] = x;
+    }
+
+    /// Return the two samples at twice the rate for the input sample `x`.
+    fn upsample(&mut self, x: T) -> (T, T) {
+        // Upsampling puts a zero between each input sample, so only every
+        // other tap lines up with an input sample.
+        self.push(x);
+        let phase = |offset: usize| -> T {
+            self.taps
+                .iter()
+                .skip(offset)
+                .step_by(2)
+                .zip(&self.history)
+                .map(|(&tap, &x)| tap * x)
+                .sum::<T>()
+                * T::from_f64(2.0)
+        };
+        (phase(0), phase(1))
+    }
+
+    /// Return one sample at half the rate for the input samples `a` and `b`.
+    fn downsample(&mut self, a: T, b: T) -> T {
+        // The output lines up with `a`, so that the delay through an
+        // upsampler and downsampler is a whole number of samples.
+        self.push(a);
+        let sample = self
+            .taps
+            .iter()
+            .zip(&self.history)
+            .map(|(&tap, &x)| tap * x)
+            .sum();
+        self.push(b);
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The index of the largest sample in `block`.
+    fn peak(block: &[f32]) -> usize {
+        (0..block.len())
+            .max_by(|&a, &b| block[a].abs().partial_cmp(&block[b].abs()).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn impulse_is_delayed_by_latency() {
+        for &factor in &[2, 4, 8] {
+            for &taps in &[17, 33, 65] {
+                let mut oversampler = Oversampler::default();
+                oversampler.set_params(factor, taps);
+                let mut block = [0.0; 256];
+                block[0] = 1.0;
+                oversampler.process_oversampled(&mut block, |x| x);
+                assert_eq!(
+                    peak(&block),
+                    oversampler.latency(),
+                    "{}x with {} taps",
+                    factor,
+                    taps
+                );
+                assert_eq!(oversampler.latency(), latency(factor, taps));
+                // The filters are linear phase, so the response is symmetric
+                // about the delayed impulse.
+                let center = peak(&block);
+                for offset in 1..8 {
+                    let (before, after) = (block[center - offset], block[center + offset]);
+                    assert!((before - after).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn passes_through_without_oversampling() {
+        let mut oversampler = Oversampler::default();
+        oversampler.set_params(1, 33);
+        let mut block = [0.5, -0.25, 1.0];
+        oversampler.process_oversampled(&mut block, |x| x);
+        assert_eq!(block, [0.5, -0.25, 1.0]);
+        assert_eq!(oversampler.latency(), 0);
+    }
+
+    #[test]
+    fn rounds_taps_to_valid_lengths() {
+        assert_eq!(valid_taps(0), 9);
+        assert_eq!(valid_taps(17), 17);
+        assert_eq!(valid_taps(20), 17);
+        assert_eq!(valid_taps(30), 33);
+        assert_eq!(valid_taps(1000), 65);
+        assert_eq!(latency(2, 20), latency(2, 17));
+    }
+}
@@ -0,0 +1,376 @@
+use std::fmt::Display;
+
+use crate::{ease_in_expo, make_strings};
+
+/// How a parameter's normalized value is spread across its range.
+#[derive(Debug, Clone, Copy)]
+pub enum Curve {
+    Linear,
+    /// Equal steps in the normalized value multiply the real value by equal
+    /// amounts. Both ends of the range must be positive.
+    Log,
+    /// The normalized value is eased in exponentially, giving most of the
+    /// travel to the low end of the range.
+    Exp,
+    /// Whole numbers from the minimum to the maximum, rounded to the nearest.
+    Discrete,
+    /// Off below one half and on above it, shown with the given labels.
+    Toggle {
+        off: &'static str,
+        on: &'static str,
+    },
+    /// One of `count` options, each given an equal share of the normalized
+    /// range. `label` returns the text for an option from its index and the
+    /// count.
+    Choice {
+        count: usize,
+        label: fn(usize, usize) -> String,
+    },
+}
+
+/// The real values a parameter covers, which the host only sees as a
+/// normalized value from 0.0 to 1.0.
+#[derive(Debug, Clone, Copy)]
+pub struct Range {
+    pub min: f32,
+    pub max: f32,
+    /// The unit shown after the value, such as "dB" or "ms"
+    pub unit: &'static str,
+    pub curve: Curve,
+    /// The normalized value, at one end of the range, which turns the
+    /// parameter off. This is shown and typed as "Off" instead of its real
+    /// value.
+    pub off: Option<f32>,
+}
+
+impl Range {
+    /// The normalized value itself, for parameters which convert it in their
+    /// own way.
+    pub const NORMALIZED: Range = Range::linear(0.0, 1.0, "");
+
+    pub const fn linear(min: f32, max: f32, unit: &'static str) -> Range {
+        Range {
+            min,
+            max,
+            unit,
+            curve: Curve::Linear,
+            off: None,
+        }
+    }
+
+    pub const fn log(min: f32, max: f32, unit: &'static str) -> Range {
+        Range {
+            min,
+            max,
+            unit,
+            curve: Curve::Log,
+            off: None,
+        }
+    }
+
+    pub const fn exp(min: f32, max: f32, unit: &'static str) -> Range {
+        Range {
+            min,
+            max,
+            unit,
+            curve: Curve::Exp,
+            off: None,
+        }
+    }
+
+    pub const fn discrete(min: f32, max: f32, unit: &'static str) -> Range {
+        Range {
+            min,
+            max,
+            unit,
+            curve: Curve::Discrete,
+            off: None,
+        }
+    }
+
+    pub const fn toggle(off: &'static str, on: &'static str) -> Range {
+        Range {
+            min: 0.0,
+            max: 1.0,
+            unit: "",
+            curve: Curve::Toggle { off, on },
+            off: None,
+        }
+    }
+
+    /// A choice between `count` options, such as the variants of an enum. The
+    /// real value is the index of the option. See `choice_label` for labeling
+    /// the options with a type's `Display` impl.
+    pub const fn choice(count: usize, label: fn(usize, usize) -> String) -> Range {
+        Range {
+            min: 0.0,
+            max: (count - 1) as f32,
+            unit: "",
+            curve: Curve::Choice { count, label },
+            off: None,
+        }
+    }
+
+    /// This range, with the normalized value `off`, which should be 0.0 or
+    /// 1.0, turning the parameter off.
+    pub const fn with_off(self, off: f32) -> Range {
+        Range {
+            off: Some(off),
+            ..self
+        }
+    }
+
+    /// Convert a normalized value to a real value.
+    pub fn to_real(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        match self.curve {
+            Curve::Linear => self.min + (self.max - self.min) * x,
+            Curve::Log => self.min * (self.max / self.min).powf(x),
+            Curve::Exp => self.min + (self.max - self.min) * ease_in_expo(x),
+            Curve::Discrete => self.min + ((self.max - self.min) * x).round(),
+            Curve::Toggle { .. } => {
+                if x > 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Curve::Choice { count, .. } => ((x * count as f32) as usize).min(count - 1) as f32,
+        }
+    }
+
+    /// Convert a real value to a normalized value. This is the inverse of
+    /// `to_real`, with values outside of the range clamped to it.
+    pub fn to_normalized(&self, value: f32) -> f32 {
+        let value = value.clamp(self.min.min(self.max), self.max.max(self.min));
+        let x = match self.curve {
+            Curve::Linear => (value - self.min) / (self.max - self.min),
+            Curve::Log => (value / self.min).ln() / (self.max / self.min).ln(),
+            Curve::Exp => {
+                let eased = (value - self.min) / (self.max - self.min);
+                (eased * (2.0f32.powf(10.0) - 1.0)).ln_1p() / (10.0 * 2.0f32.ln())
+            }
+            Curve::Discrete => (value.round() - self.min) / (self.max - self.min),
+            Curve::Toggle { .. } => value.round(),
+            // The middle of the option's share, so that hosts which nudge
+            // the value slightly don't flip to the next option.
+            Curve::Choice { count, .. } => (value.round() + 0.5) / count as f32,
+        };
+        if x.is_finite() {
+            x.clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Move a normalized value onto the nearest value this range can take.
+    /// Continuous ranges are left as they are.
+    pub fn snap(&self, x: f32) -> f32 {
+        match self.curve {
+            Curve::Linear | Curve::Log | Curve::Exp => x,
+            Curve::Discrete | Curve::Toggle { .. } | Curve::Choice { .. } => {
+                self.to_normalized(self.to_real(x))
+            }
+        }
+    }
+
+    /// Parse text typed into the host, such as "-6 dB", "250ms" or "1.5 kHz",
+    /// into a normalized value. The unit may be left out or shortened. A
+    /// percentage is taken as a fraction of the real value, which is how
+    /// parameters without a unit show themselves. Returns None if the text
+    /// isn't a number in this range's unit. Toggles and choices also accept
+    /// their labels, and choices labeled with a number, such as "16 bits" or
+    /// "CC 64", accept the number on its own. Ranges which can be turned off
+    /// accept "off".
+    pub fn parse(&self, text: &str) -> Option<f32> {
+        let text = text.trim().to_lowercase();
+        if let Some(off) = self.off {
+            if text == "off" {
+                return Some(off);
+            }
+        }
+        match self.curve {
+            Curve::Toggle { off, on } => {
+                if text == on.to_lowercase() || ["on", "true", "yes"].contains(&text.as_str()) {
+                    return Some(1.0);
+                } else if text == off.to_lowercase()
+                    || ["off", "false", "no"].contains(&text.as_str())
+                {
+                    return Some(0.0);
+                }
+            }
+            Curve::Choice { count, label } => {
+                let matches = |index| {
+                    let label = label(index, count).to_lowercase();
+                    let number = label
+                        .split(|c: char| !c.is_ascii_digit() && c != '.')
+                        .find(|number| !number.is_empty())
+                        .unwrap_or("");
+                    label == text || (!number.is_empty() && number == text)
+                };
+                if let Some(index) = (0..count).find(|&index| matches(index)) {
+                    return Some(self.to_normalized(index as f32));
+                }
+            }
+            _ => (),
+        }
+        let unit = self.unit.to_lowercase();
+        // Split the number from whatever follows it.
+        let end = text.rfind(|c: char| c.is_ascii_digit() || c == '.')? + 1;
+        let (number, suffix) = text.split_at(end);
+        let suffix = suffix.trim();
+        let scale = if suffix.is_empty() {
+            1.0
+        } else if suffix == "%" && !unit.contains('%') {
+            0.01
+        } else if unit.starts_with(suffix) {
+            1.0
+        } else if (unit == "ms" && suffix == "s")
+            || (suffix.len() > 1 && suffix.starts_with('k') && unit.starts_with(&suffix[1..]))
+        {
+            // Seconds when milliseconds are expected, or kilohertz when hertz are
+            1000.0
+        } else {
+            return None;
+        };
+        let value: f32 = number.trim().parse().ok()?;
+        if value.is_finite() {
+            Some(self.to_normalized(value * scale))
+        } else {
+            None
+        }
+    }
+
+    /// The text the host shows for a real value, as a (value, unit) pair.
+    pub fn strings(&self, value: f32) -> (String, String) {
+        if self.off.map(|off| self.to_real(off)) == Some(value) {
+            return ("Off".to_string(), "".to_string());
+        }
+        match self.curve {
+            Curve::Discrete => (format!("{}", value as i64), self.unit.to_string()),
+            Curve::Toggle { off, on } => {
+                let label = if value > 0.5 { on } else { off };
+                (label.to_string(), "".to_string())
+            }
+            Curve::Choice { count, label } => (label(value as usize, count), "".to_string()),
+            _ => make_strings(value, self.unit),
+        }
+    }
+}
+
+/// Label option `index` of `count` with `T`'s `Display` impl, for types which
+/// convert from a normalized value by splitting it into `count` equal shares.
+pub fn choice_label<T: From<f32> + Display>(index: usize, count: usize) -> String {
+    T::from((index as f32 + 0.5) / count as f32).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse the text `range` shows for the normalized value `x`, with and
+    /// without its unit.
+    fn round_trip(range: &Range, x: f32) -> [Option<f32>; 2] {
+        let (value, unit) = range.strings(range.to_real(x));
+        [
+            range.parse(&value),
+            range.parse(&format!("{} {}", value, unit)),
+        ]
+    }
+
+    fn assert_round_trips(range: Range, values: &[f32]) {
+        for &x in values {
+            let expected = range.snap(x);
+            for parsed in round_trip(&range, x).iter() {
+                let parsed = parsed.expect("shown text should parse");
+                // The text is rounded to two decimal places.
+                assert!(
+                    (range.to_real(parsed) - range.to_real(expected)).abs() <= 0.005,
+                    "{:?} at {} parsed back as {}",
+                    range,
+                    x,
+                    parsed
+                );
+            }
+        }
+    }
+
+    const VALUES: [f32; 5] = [0.0, 0.25, 0.5, 0.8, 1.0];
+
+    #[test]
+    fn percentages_round_trip() {
+        assert_round_trips(Range::linear(0.0, 100.0, "%"), &VALUES);
+        assert_round_trips(Range::linear(-100.0, 100.0, "%"), &VALUES);
+        assert_round_trips(Range::linear(0.0, 100.0, "% Wet"), &VALUES);
+    }
+
+    #[test]
+    fn continuous_ranges_round_trip() {
+        assert_round_trips(Range::linear(-24.0, 24.0, "dB"), &VALUES);
+        assert_round_trips(Range::log(20.0, 20000.0, "Hz"), &VALUES);
+        assert_round_trips(Range::exp(0.0, 500.0, "ms"), &[0.25, 0.5, 0.8, 1.0]);
+    }
+
+    #[test]
+    fn stepped_ranges_round_trip() {
+        assert_round_trips(Range::discrete(-12.0, 12.0, "st"), &VALUES);
+        assert_round_trips(Range::toggle("Off", "On"), &[0.0, 1.0]);
+        let bits = |index: usize, _| match index {
+            0 => "Off".to_string(),
+            index => format!("{} bits", index * 8),
+        };
+        assert_round_trips(Range::choice(4, bits), &VALUES);
+        let factor = |index: usize, _| format!("{}x", 1 << index);
+        assert_round_trips(Range::choice(5, factor), &VALUES);
+    }
+
+    #[test]
+    fn off_values_round_trip() {
+        let range = Range::linear(-24.0, 0.0, "dB").with_off(1.0);
+        assert_eq!(range.strings(0.0), ("Off".to_string(), "".to_string()));
+        assert_eq!(range.parse("Off"), Some(1.0));
+        assert_round_trips(range, &VALUES);
+        let range = Range::linear(-60.0, 0.0, "dB").with_off(0.0);
+        assert_eq!(range.strings(-60.0), ("Off".to_string(), "".to_string()));
+        assert_eq!(range.parse("off"), Some(0.0));
+        assert_eq!(range.to_real(range.parse("-59").unwrap()), -59.0);
+        assert_round_trips(range, &VALUES);
+        assert_eq!(Range::linear(-60.0, 0.0, "dB").parse("off"), None);
+    }
+
+    #[test]
+    fn percent_of_unitless_range() {
+        let range = Range::NORMALIZED;
+        assert_eq!(range.parse("35%"), Some(0.35));
+        assert_eq!(range.parse("0.35"), Some(0.35));
+    }
+
+    #[test]
+    fn choices_accept_the_number_in_their_label() {
+        let bits = |index: usize, _| match index {
+            0 => "Off".to_string(),
+            index => format!("{} bits", index * 8),
+        };
+        let range = Range::choice(4, bits);
+        assert_eq!(range.parse("16"), range.parse("16 bits"));
+        assert_eq!(range.parse("off"), Some(range.to_normalized(0.0)));
+        assert_eq!(range.to_real(range.parse("24").unwrap()), 3.0);
+        let cc = |index: usize, _| match index {
+            0 => "Off".to_string(),
+            index => format!("CC {}", index - 1),
+        };
+        let range = Range::choice(129, cc);
+        assert_eq!(range.to_real(range.parse("64").unwrap()), 65.0);
+        assert_eq!(range.parse("cc 64"), range.parse("64"));
+    }
+
+    #[test]
+    fn scaled_units() {
+        let range = Range::linear(0.0, 2000.0, "ms");
+        assert_eq!(range.to_real(range.parse("1.5 s").unwrap()), 1500.0);
+        let range = Range::log(20.0, 20000.0, "Hz");
+        let parsed = range.to_real(range.parse("2 kHz").unwrap());
+        assert!((parsed - 2000.0).abs() < 0.1);
+        assert_eq!(range.parse("2 dB"), None);
+    }
+}
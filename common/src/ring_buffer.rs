@@ -0,0 +1,793 @@
+use std::f32::consts::FRAC_PI_2;
+
+use crate::{flush_denormal, rng::Rng};
+
+/// The length of the crossfade when engaging or releasing the trigger, in
+/// milliseconds.
+const TRIGGER_FADE_MS: f32 = 5.0;
+/// The length of the fades at the loop edges, in samples.
+const EDGE_FADE: f32 = 32.0;
+/// The furthest, as a ratio, that the pitch step can move the playback speed.
+const MAX_STEP_RATIO: f32 = 16.0;
+/// The furthest, in samples, that zero-crossing snap moves a loop point.
+const MAX_SNAP: usize = 512;
+/// The most voices which can play the loop at once.
+pub const MAX_VOICES: usize = 4;
+
+/// A buffer which, while triggered, captures its input and plays it back as a
+/// loop. The loop can be resized, sliced, reversed, and played back at other
+/// speeds while playing.
+///
+/// Positions within the loop are mapped onto the buffer relative to where the
+/// buffer was written to when triggered, so that with pre-roll, the loop can
+/// end at the trigger instead of starting there.
+pub struct RingBuffer {
+    buffer: Vec<f32>,
+    // The index into `buffer` of the next sample to be recorded while
+    // untriggered, with pre-roll on.
+    write_pos: usize,
+    // The value of `write_pos` when last triggered.
+    trigger_pos: usize,
+    // If true, record continuously while untriggered.
+    preroll: bool,
+    // If true, the current loop is the audio from before it was triggered.
+    preroll_loop: bool,
+    // The index within the loop of the next sample to be captured.
+    needle: usize,
+    // The voices reading the loop. Only the first `voice_count` play.
+    voices: Vec<Voice>,
+    voice_count: usize,
+    trigger: bool,
+    // If true, the loop plays backwards once it has been captured.
+    reverse: bool,
+    // If true, every other repeat plays in the opposite direction.
+    ping_pong: bool,
+    // How far into the captured loop playback starts, as a fraction of the
+    // loop length.
+    offset: f32,
+    // If true, the loop points are moved to the nearest zero crossings.
+    snap: bool,
+    // The number of samples the playhead advances per sample.
+    speed: f32,
+    // The ratio to multiply the speed by at the end of each repeat.
+    step_ratio: f32,
+    // If true, fade out at the loop edges, so that a changing loop length
+    // doesn't click.
+    edge_fade: bool,
+    // The number of equal slices the loop is divided into.
+    slices: usize,
+    // The index of the slice which repeats.
+    slice: usize,
+    // The number of repeats after which to go back to the input, or None to
+    // repeat for as long as triggered.
+    max_repeats: Option<usize>,
+    // How much of the loop is in the output, from 0.0 to 1.0
+    fade: f32,
+    // How much `fade` moves per sample
+    fade_step: f32,
+    // If true, the next change in loop length fades the loop out and back in.
+    smooth_resize: bool,
+    // How much of the loop is let through while resizing smoothly, from 0.0
+    // to 1.0.
+    resize_fade: f32,
+    // If true, keep recording the input into the loop as it repeats.
+    overdub: bool,
+    // How much of the existing loop is kept when overdubbing, from 0.0 to 1.0
+    feedback: f32,
+    // If true, each repeat plays a random slice instead of `slice`.
+    shuffle: bool,
+    // The fraction of each repeat which plays before being gated off, or 1.0
+    // to play all of each repeat.
+    gate: f32,
+    // The length, in samples, of the slowdown on release, or 0 to release
+    // immediately.
+    tape_stop: usize,
+    // True while slowing down after being released.
+    stopping: bool,
+    // The speed, relative to normal, while slowing down.
+    stop_rate: f32,
+}
+
+impl RingBuffer {
+    /// Create a ring buffer able to hold loops up to `capacity` samples long.
+    /// `seed` seeds the random choices of the voices, each of which gets its
+    /// own sequence.
+    pub fn new(capacity: usize, seed: u32) -> RingBuffer {
+        let mut seeds = Rng::new(seed);
+        RingBuffer {
+            buffer: vec![0.0; capacity],
+            write_pos: 0,
+            trigger_pos: 0,
+            preroll: false,
+            preroll_loop: false,
+            needle: 0,
+            voices: (0..MAX_VOICES)
+                .map(|_| Voice::new(capacity, seeds.next_u32()))
+                .collect(),
+            voice_count: 1,
+            trigger: false,
+            reverse: false,
+            ping_pong: false,
+            offset: 0.0,
+            snap: false,
+            speed: 1.0,
+            step_ratio: 1.0,
+            edge_fade: false,
+            slices: 1,
+            slice: 0,
+            max_repeats: None,
+            fade: 0.0,
+            fade_step: 1.0,
+            smooth_resize: false,
+            resize_fade: 1.0,
+            overdub: false,
+            feedback: 0.0,
+            shuffle: false,
+            gate: 1.0,
+            tape_stop: 0,
+            stopping: false,
+            stop_rate: 1.0,
+        }
+    }
+
+    /// Return the next sample from the ring buffer, optionally also consuming a
+    /// sample in the process. Engaging and releasing the trigger crossfades
+    /// between the input and the loop.
+    pub fn next(&mut self, input: f32) -> f32 {
+        if self.preroll && !self.trigger {
+            self.buffer[self.write_pos] = input;
+            self.write_pos = (self.write_pos + 1) % self.buffer.len();
+        }
+
+        if self.stopping {
+            self.stop_rate -= 1.0 / self.tape_stop as f32;
+            if self.stop_rate <= 0.0 {
+                self.stop_rate = 0.0;
+                self.stopping = false;
+                self.trigger = false;
+            }
+        }
+
+        // Fade the loop out before changing its length, then back in, so the
+        // jump in length doesn't click.
+        if self.smooth_resize {
+            self.resize_fade = (self.resize_fade - self.fade_step).max(0.0);
+            if self.resize_fade <= 0.0 || !self.is_looping() {
+                for voice in &mut self.voices {
+                    voice.size = voice.target_size;
+                }
+                self.smooth_resize = false;
+            }
+        } else {
+            self.resize_fade = (self.resize_fade + self.fade_step).min(1.0);
+        }
+
+        let target = if self.trigger { 1.0 } else { 0.0 };
+        self.fade = if self.fade < target {
+            (self.fade + self.fade_step).min(target)
+        } else {
+            (self.fade - self.fade_step).max(target)
+        };
+        if self.fade <= 0.0 {
+            return input;
+        }
+
+        let (sample, looping) = self.next_loop(input);
+        let mix = self.fade * self.resize_fade;
+        // While the loop is repeating it isn't correlated with the input, so
+        // the crossfade is equal-power. While the loop side is still passing
+        // the input through, an equal-power crossfade would boost it, so the
+        // crossfade is equal-gain instead.
+        let angle = mix * FRAC_PI_2;
+        let dry_gain = (1.0 - mix) + (angle.cos() - (1.0 - mix)) * looping;
+        let wet_gain = mix + (angle.sin() - mix) * looping;
+        input * dry_gain + sample * wet_gain
+    }
+
+    // Return the next sample of the loop, and how much of it is the loop
+    // rather than the input passing through, from 0.0 to 1.0. This keeps going
+    // after the trigger is released so that the release can fade out.
+    fn next_loop(&mut self, input: f32) -> (f32, f32) {
+        // With pre-roll, the loop has already been captured.
+        if !self.preroll_loop {
+            // If the needle hasn't been through the entire buffer yet, write
+            // the input. This allows `size` to increase and play the audio that
+            // "would have" been there if size was larger initially.
+            if self.needle < self.buffer.len() {
+                let i = self.index(self.needle, self.voices[0].size);
+                self.buffer[i] = input;
+            }
+            self.needle += 1;
+        }
+
+        // Take the voices out so that each can be played while reading the
+        // rest of the ring buffer. This doesn't allocate.
+        let mut voices = std::mem::take(&mut self.voices);
+        let mut sample = 0.0;
+        let mut looping: f32 = 0.0;
+        for (i, voice) in voices.iter_mut().enumerate().take(self.voice_count) {
+            // Only the first voice overdubs, so that the voices don't write
+            // over each other.
+            let (voice_sample, mix) = self.play(voice, input, i == 0);
+            sample += voice.level * voice_sample;
+            looping = looping.max(mix);
+        }
+        self.voices = voices;
+        // The input passes through once while no voice is looping, rather
+        // than once for every voice.
+        (sample + input * (1.0 - looping), looping)
+    }
+
+    // Return the next sample of a single voice's loop, and how much of the
+    // voice is the loop rather than the input, from 0.0 to 1.0. The sample is
+    // already scaled by that amount.
+    fn play(&mut self, voice: &mut Voice, input: f32, overdub: bool) -> (f32, f32) {
+        // The first pass through the loop plays the input as it's being
+        // captured, since the rest of the loop doesn't exist yet.
+        if !self.preroll_loop && self.needle <= voice.size {
+            return (0.0, 0.0);
+        }
+        // Once the loop has repeated enough times, go back to the input.
+        if self.max_repeats.is_some_and(|max| voice.repeats >= max) {
+            return (0.0, 0.0);
+        }
+
+        // Only the selected slice of the loop repeats.
+        let slice = if self.shuffle {
+            voice.shuffled_slice
+        } else {
+            self.slice
+        };
+        let length = (voice.size / self.slices).max(1);
+        // The offset rotates the loop, wrapping around to the beginning.
+        let shift = (self.offset * voice.size as f32) as usize;
+        let start = slice.min(self.slices - 1) * length + shift;
+        // Snapped loop points are found once per repeat, since the search is
+        // too slow to do every sample.
+        let size = voice.size;
+        let (start, length) = if self.snap {
+            *voice
+                .snap
+                .get_or_insert_with(|| self.snap_loop(start, length, size))
+        } else {
+            (start, length)
+        };
+        let length = length as f32;
+
+        voice.playhead = voice.playhead.rem_euclid(length);
+        // In reverse, each pass starts at the end of the slice.
+        let reverse = self.reverse != (self.ping_pong && voice.repeats % 2 == 1);
+        let position = if reverse {
+            (length - 1.0 - voice.playhead).rem_euclid(length)
+        } else {
+            voice.playhead
+        };
+        let sample = self.read(start as f32 + position, voice.size);
+        if self.overdub && overdub {
+            let i = self.index((start + position as usize) % voice.size, voice.size);
+            // With feedback, the old loop fades out a little on every pass.
+            self.buffer[i] = flush_denormal(input + (self.buffer[i] - input) * self.feedback);
+        }
+        let fade = EDGE_FADE.min(length / 4.0).max(1.0);
+        let sample = if self.edge_fade {
+            let distance = voice.playhead.min(length - voice.playhead);
+            sample * (distance / fade).min(1.0)
+        } else {
+            sample
+        };
+        // Chop each repeat into a burst, with short fades so the gate doesn't
+        // click.
+        let sample = if self.gate < 1.0 {
+            let attack = voice.playhead / fade;
+            let release = (self.gate * length - voice.playhead) / fade;
+            sample * attack.min(release).clamp(0.0, 1.0)
+        } else {
+            sample
+        };
+        // Crossfade back into the input at the end of the last repeat.
+        let mix = if self.max_repeats == Some(voice.repeats + 1) {
+            ((length - voice.playhead) / fade).min(1.0)
+        } else {
+            1.0
+        };
+
+        voice.playhead += self.speed * voice.step_rate * self.stop_rate;
+        if voice.playhead >= length {
+            voice.repeats += 1;
+            voice.snap = None;
+            if self.shuffle {
+                voice.shuffle_slice(self.slices);
+            }
+            voice.step_rate =
+                (voice.step_rate * self.step_ratio).clamp(1.0 / MAX_STEP_RATIO, MAX_STEP_RATIO);
+        }
+        (sample * mix, mix)
+    }
+
+    // Return the index into `buffer` of the sample `i` samples into a loop
+    // `size` samples long.
+    fn index(&self, i: usize, size: usize) -> usize {
+        let capacity = self.buffer.len();
+        let start = if self.preroll_loop {
+            self.trigger_pos + capacity - size
+        } else {
+            self.trigger_pos
+        };
+        (start + i) % capacity
+    }
+
+    // Return the start and length of a loop moved so that both ends are on
+    // rising zero crossings, in a loop `size` samples long. Ends without a
+    // zero crossing nearby are left alone.
+    fn snap_loop(&self, start: usize, length: usize, size: usize) -> (usize, usize) {
+        let range = MAX_SNAP.min(length / 4);
+        let end = self.nearest_crossing(start + length, range, size);
+        let start = self.nearest_crossing(start, range, size);
+        let length = (end - start).max(1) as usize;
+        (start.rem_euclid(size as isize) as usize, length)
+    }
+
+    // Return the position of the rising zero crossing closest to `position`,
+    // up to `range` samples away, or `position` if there isn't one.
+    fn nearest_crossing(&self, position: usize, range: usize, size: usize) -> isize {
+        let position = position as isize;
+        let sample = |i: isize| self.buffer[self.index(i.rem_euclid(size as isize) as usize, size)];
+        let rising = |i: isize| sample(i - 1) < 0.0 && sample(i) >= 0.0;
+        (0..=range as isize)
+            .flat_map(|distance| [position - distance, position + distance])
+            .find(|&i| rising(i))
+            .unwrap_or(position)
+    }
+
+    // Return true if the loop has been captured and is repeating.
+    fn is_looping(&self) -> bool {
+        self.preroll_loop || self.needle > self.voices[0].size
+    }
+
+    // Return the sample at a fractional position within the loop, using cubic
+    // interpolation between the neighboring samples.
+    fn read(&self, position: f32, size: usize) -> f32 {
+        let index = position as usize;
+        let t = position - index as f32;
+        let sample = |offset: isize| {
+            let i = (index as isize + offset).rem_euclid(size as isize) as usize;
+            self.buffer[self.index(i, size)]
+        };
+        let (y0, y1, y2, y3) = (sample(-1), sample(0), sample(1), sample(2));
+
+        // Catmull-Rom spline
+        let a = -0.5 * y0 + 1.5 * y1 - 1.5 * y2 + 0.5 * y3;
+        let b = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+        let c = -0.5 * y0 + 0.5 * y2;
+        ((a * t + b) * t + c) * t + y1
+    }
+
+    /// Returns how much of the loop is in the output, from 0.0 (untriggered)
+    /// to 1.0 (triggered), which moves smoothly when the trigger changes.
+    pub fn engaged(&self) -> f32 {
+        self.fade
+    }
+
+    /// Replace the contents of `out` with the captured loop of the main voice.
+    /// If the loop is still being captured, only the part captured so far is
+    /// copied. This doesn't allocate if `out` has room for the whole loop.
+    pub fn copy_loop(&self, out: &mut Vec<f32>) {
+        let size = self.voices[0].size;
+        let captured = if self.preroll_loop {
+            size
+        } else {
+            self.needle.min(size)
+        };
+        out.clear();
+        out.extend((0..captured).map(|i| self.buffer[self.index(i, size)]));
+    }
+
+    /// Returns the number of times the main voice's loop has repeated since
+    /// being triggered.
+    pub fn repeats(&self) -> usize {
+        self.voices[0].repeats
+    }
+
+    /// Set the loop length, in samples. This is clamped to the capacity.
+    pub fn set_size(&mut self, new_size: usize) {
+        let size = new_size.clamp(1, self.buffer.len());
+        self.voices[0].resize(size, self.smooth_resize);
+    }
+
+    /// Fade the loop out before the next change in loop length and back in
+    /// after, instead of changing the length immediately. Lengths set while
+    /// fading out are applied once the loop is silent.
+    pub fn resize_smoothly(&mut self) {
+        self.smooth_resize = true;
+    }
+
+    /// Set the number of voices which play the loop at once, from 1 to
+    /// `MAX_VOICES`.
+    pub fn set_voice_count(&mut self, voice_count: usize) {
+        self.voice_count = voice_count.clamp(1, MAX_VOICES);
+    }
+
+    /// Set the loop length, in samples, and level of one of the extra voices.
+    /// Voice 0 is the main voice, whose length is set by `set_size` and which
+    /// always plays at full level.
+    pub fn set_voice(&mut self, index: usize, size: usize, level: f32) {
+        let size = size.clamp(1, self.buffer.len());
+        let voice = &mut self.voices[index];
+        voice.resize(size, self.smooth_resize);
+        voice.level = level;
+    }
+
+    pub fn set_reverse(&mut self, reverse: bool) {
+        self.reverse = reverse;
+    }
+
+    /// Set how far into the captured loop playback starts, from 0.0 to 1.0 of
+    /// the loop length.
+    pub fn set_offset(&mut self, offset: f32) {
+        self.offset = offset.clamp(0.0, 1.0);
+    }
+
+    /// If `snap` is true, the start and end of each repeat are moved to the
+    /// nearest zero crossings in the loop, so that unsynced loops don't click.
+    pub fn set_snap(&mut self, snap: bool) {
+        self.snap = snap;
+    }
+
+    /// If `ping_pong` is true, the first repeat plays in the direction set
+    /// by `set_reverse`, and each repeat after plays in the other direction.
+    pub fn set_ping_pong(&mut self, ping_pong: bool) {
+        self.ping_pong = ping_pong;
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// Reallocate the buffer to hold `capacity` samples, discarding anything
+    /// captured. This allocates, so it shouldn't be called while processing.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        if capacity == self.buffer.len() {
+            return;
+        }
+        self.buffer = vec![0.0; capacity];
+        self.write_pos = 0;
+        self.trigger_pos = 0;
+        self.needle = 0;
+        self.trigger = false;
+        self.fade = 0.0;
+        for voice in &mut self.voices {
+            voice.size = voice.size.clamp(1, capacity);
+            voice.target_size = voice.target_size.clamp(1, capacity);
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.fade_step = 1.0 / (TRIGGER_FADE_MS / 1000.0 * sample_rate).max(1.0);
+    }
+
+    /// If `preroll` is true, the input is recorded continuously while
+    /// untriggered, and triggering loops the audio from just before the
+    /// trigger instead of capturing from the trigger onwards.
+    pub fn set_preroll(&mut self, preroll: bool) {
+        self.preroll = preroll;
+    }
+
+    /// If `overdub` is true, the input keeps being recorded into the loop as
+    /// it plays, blended with what was already there by `feedback`.
+    pub fn set_overdub(&mut self, overdub: bool, feedback: f32) {
+        self.overdub = overdub;
+        self.feedback = feedback;
+    }
+
+    pub fn set_max_repeats(&mut self, max_repeats: Option<usize>) {
+        self.max_repeats = max_repeats;
+    }
+
+    pub fn set_slice(&mut self, slices: usize, slice: usize) {
+        self.slices = slices.max(1);
+        self.slice = slice;
+    }
+
+    /// Set how long, in samples, the loop takes to slow to a stop after the
+    /// trigger is released, like a tape machine being switched off. If 0, the
+    /// loop fades straight back to the input instead.
+    pub fn set_tape_stop(&mut self, tape_stop: usize) {
+        self.tape_stop = tape_stop;
+    }
+
+    /// Set the fraction of each repeat which plays before being gated off.
+    pub fn set_gate(&mut self, gate: f32) {
+        self.gate = gate;
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+    }
+
+    pub fn set_edge_fade(&mut self, edge_fade: bool) {
+        self.edge_fade = edge_fade;
+    }
+
+    pub fn set_pitch_step(&mut self, semitones: f32) {
+        self.step_ratio = 2.0f32.powf(semitones / 12.0);
+    }
+
+    pub fn set_triggered(&mut self) {
+        self.preroll_loop = self.preroll;
+        self.trigger_pos = self.write_pos;
+        self.needle = 0;
+        for voice in &mut self.voices {
+            voice.reset(self.slice);
+            voice.size = voice.target_size;
+        }
+        self.smooth_resize = false;
+        self.stopping = false;
+        self.stop_rate = 1.0;
+        self.trigger = true;
+    }
+
+    pub fn set_untriggered(&mut self) {
+        // There's only a loop to slow down once the first pass has been
+        // captured.
+        if self.tape_stop > 0 && self.trigger && self.is_looping() {
+            self.stopping = true;
+        } else {
+            self.trigger = false;
+        }
+    }
+}
+
+/// A needle reading the captured loop. Each voice plays the same captured
+/// audio, but with its own loop length.
+struct Voice {
+    // The position within the loop of the next sample to be played, once the
+    // loop has been captured. This is fractional so that the loop can be
+    // played back at other speeds.
+    playhead: f32,
+    // The loop length, in samples.
+    size: usize,
+    // The loop length to change to once the loop has faded out, when
+    // resizing smoothly.
+    target_size: usize,
+    level: f32,
+    // The product of the step ratios of every repeat so far.
+    step_rate: f32,
+    // The number of times the loop has repeated since being triggered.
+    repeats: usize,
+    // The slice the current repeat plays when shuffling.
+    shuffled_slice: usize,
+    // The start and length of the current repeat, once the loop points have
+    // been snapped to zero crossings.
+    snap: Option<(usize, usize)>,
+    rng: Rng,
+}
+
+impl Voice {
+    fn new(size: usize, seed: u32) -> Voice {
+        Voice {
+            playhead: 0.0,
+            size,
+            target_size: size,
+            level: 1.0,
+            step_rate: 1.0,
+            repeats: 0,
+            shuffled_slice: 0,
+            snap: None,
+            rng: Rng::new(seed),
+        }
+    }
+
+    fn resize(&mut self, size: usize, smooth: bool) {
+        self.target_size = size;
+        if !smooth {
+            self.size = size;
+        }
+    }
+
+    fn reset(&mut self, slice: usize) {
+        self.playhead = 0.0;
+        self.step_rate = 1.0;
+        self.repeats = 0;
+        self.shuffled_slice = slice;
+        self.snap = None;
+    }
+
+    // Pick a random slice for the next repeat. The same slice never plays
+    // twice in a row, since that sounds like a mistake rather than a shuffle.
+    fn shuffle_slice(&mut self, slices: usize) {
+        if slices > 1 {
+            let slice = self.rng.next_u32() as usize % (slices - 1);
+            self.shuffled_slice = if slice >= self.shuffled_slice {
+                slice + 1
+            } else {
+                slice
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn ring_buffer(size: usize) -> RingBuffer {
+        let mut ring_buffer = RingBuffer::new(SAMPLE_RATE as usize, 0);
+        ring_buffer.set_sample_rate(SAMPLE_RATE);
+        ring_buffer.set_size(size);
+        ring_buffer
+    }
+
+    // Run `samples` samples of `input` through the ring buffer, checking that
+    // the output never goes above the input.
+    fn assert_no_boost(ring_buffer: &mut RingBuffer, input: f32, samples: usize) {
+        for i in 0..samples {
+            let output = ring_buffer.next(input);
+            assert!(
+                output <= input + 1e-6,
+                "sample {}: {} > {}",
+                i,
+                output,
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn release_while_capturing_does_not_boost() {
+        let mut ring_buffer = ring_buffer(10000);
+        ring_buffer.set_triggered();
+        assert_no_boost(&mut ring_buffer, 0.5, 1000);
+        ring_buffer.set_untriggered();
+        assert_no_boost(&mut ring_buffer, 0.5, 1000);
+        assert_eq!(ring_buffer.engaged(), 0.0);
+    }
+
+    #[test]
+    fn release_after_last_repeat_does_not_boost() {
+        let mut ring_buffer = ring_buffer(1000);
+        ring_buffer.set_max_repeats(Some(2));
+        ring_buffer.set_triggered();
+        assert_no_boost(&mut ring_buffer, 0.5, 4000);
+        ring_buffer.set_untriggered();
+        assert_no_boost(&mut ring_buffer, 0.5, 1000);
+    }
+
+    #[test]
+    fn voices_pass_the_input_through_once() {
+        for voice_count in 2..=MAX_VOICES {
+            let mut ring_buffer = ring_buffer(2000);
+            ring_buffer.set_voice_count(voice_count);
+            for i in 1..voice_count {
+                ring_buffer.set_voice(i, 2000, 0.5);
+            }
+            ring_buffer.set_max_repeats(Some(1));
+            ring_buffer.set_triggered();
+            // Capturing
+            assert_no_boost(&mut ring_buffer, 0.5, 2000);
+            // Looping, with every voice layered on top of each other
+            for _ in 0..2000 {
+                ring_buffer.next(0.5);
+            }
+            // Done repeating
+            assert_no_boost(&mut ring_buffer, 0.5, 1000);
+        }
+    }
+
+    fn ramp(i: usize) -> f32 {
+        i as f32 / 1000.0
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn engaging_and_releasing_fade() {
+        let mut ring_buffer = ring_buffer(1000);
+        let fade_length = (TRIGGER_FADE_MS / 1000.0 * SAMPLE_RATE) as usize;
+        ring_buffer.set_triggered();
+        ring_buffer.next(0.0);
+        assert!(ring_buffer.engaged() > 0.0 && ring_buffer.engaged() < 1.0);
+        for _ in 0..=fade_length {
+            ring_buffer.next(0.0);
+        }
+        assert_eq!(ring_buffer.engaged(), 1.0);
+
+        ring_buffer.set_untriggered();
+        for _ in 0..=fade_length {
+            ring_buffer.next(0.0);
+        }
+        assert_eq!(ring_buffer.engaged(), 0.0);
+        assert_eq!(ring_buffer.next(0.25), 0.25);
+    }
+
+    #[test]
+    fn loop_repeats_captured_input() {
+        let mut ring_buffer = ring_buffer(400);
+        ring_buffer.set_triggered();
+        for i in 0..400 {
+            assert_close(ring_buffer.next(ramp(i)), ramp(i));
+        }
+        for i in 0..1200 {
+            assert_close(ring_buffer.next(0.0), ramp(i % 400));
+        }
+        assert_eq!(ring_buffer.repeats(), 3);
+    }
+
+    #[test]
+    fn reverse_plays_loop_backwards() {
+        let mut ring_buffer = ring_buffer(400);
+        ring_buffer.set_reverse(true);
+        ring_buffer.set_triggered();
+        for i in 0..400 {
+            ring_buffer.next(ramp(i));
+        }
+        for i in 0..800 {
+            assert_close(ring_buffer.next(0.0), ramp(399 - i % 400));
+        }
+    }
+
+    #[test]
+    fn ping_pong_alternates_direction() {
+        let mut ring_buffer = ring_buffer(400);
+        ring_buffer.set_ping_pong(true);
+        ring_buffer.set_triggered();
+        for i in 0..400 {
+            ring_buffer.next(ramp(i));
+        }
+        for i in 0..400 {
+            assert_close(ring_buffer.next(0.0), ramp(i));
+        }
+        for i in 0..400 {
+            assert_close(ring_buffer.next(0.0), ramp(399 - i));
+        }
+    }
+
+    #[test]
+    fn voices_loop_at_their_own_lengths() {
+        let mut ring_buffer = ring_buffer(400);
+        ring_buffer.set_voice_count(2);
+        ring_buffer.set_voice(1, 200, 0.5);
+        ring_buffer.set_triggered();
+        for i in 0..400 {
+            ring_buffer.next(ramp(i));
+        }
+        // The shorter voice started repeating halfway through capturing the
+        // main voice's loop.
+        for i in 0..800 {
+            let expected = ramp(i % 400) + 0.5 * ramp((i + 200) % 200);
+            assert_close(ring_buffer.next(0.0), expected);
+        }
+    }
+
+    #[test]
+    fn voices_are_seeded_separately() {
+        let mut ring_buffer = RingBuffer::new(400, 0);
+        let first: Vec<u32> = ring_buffer
+            .voices
+            .iter_mut()
+            .map(|voice| voice.rng.next_u32())
+            .collect();
+        for (i, value) in first.iter().enumerate() {
+            assert!(!first[..i].contains(value));
+        }
+        let mut other = RingBuffer::new(400, 1);
+        assert_ne!(other.voices[0].rng.next_u32(), first[0]);
+    }
+
+    #[test]
+    fn max_repeats_returns_to_input() {
+        let mut ring_buffer = ring_buffer(400);
+        ring_buffer.set_max_repeats(Some(2));
+        ring_buffer.set_triggered();
+        for i in 0..1200 {
+            ring_buffer.next(ramp(i % 400));
+        }
+        assert_eq!(ring_buffer.repeats(), 2);
+        for i in 0..400 {
+            assert_eq!(ring_buffer.next(ramp(i)), ramp(i));
+        }
+    }
+}
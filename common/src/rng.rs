@@ -0,0 +1,53 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A small, fast, seedable pseudorandom number generator (xorshift32). The
+/// same seed always produces the same sequence, which makes randomized effects
+/// reproducible. This is not suitable for anything besides audio.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Rng {
+        // Xorshift gets stuck on a state of zero, so scramble the seed.
+        let state = seed ^ 0x9E37_79B9;
+        Rng {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Returns a value uniformly distributed in [0.0, 1.0)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Returns a value uniformly distributed in [-1.0, 1.0)
+    pub fn next_bipolar(&mut self) -> f32 {
+        self.next_f32() * 2.0 - 1.0
+    }
+}
+
+/// A seed which is different every time this is called, for randomness which
+/// should differ between plugin instances, so that two copies of an effect
+/// don't make the same random choices in lockstep.
+pub fn instance_seed() -> u32 {
+    static INSTANCES: AtomicU32 = AtomicU32::new(0);
+    let count = INSTANCES.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.subsec_nanos());
+    Rng::new(count.wrapping_mul(0x9E37_79B9) ^ nanos).next_u32()
+}
@@ -0,0 +1,97 @@
+/// How a `SmoothedParam` moves towards its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Smoothing {
+    /// Move a fixed fraction of the remaining distance each sample, with the
+    /// smoothing time as the time constant.
+    OnePole,
+    /// Move in a straight line, arriving after exactly the smoothing time.
+    Linear,
+}
+
+/// A value which glides towards its target instead of jumping to it, so that
+/// automating a parameter doesn't cause zipper noise.
+#[derive(Debug, Clone, Copy)]
+pub struct SmoothedParam {
+    value: f32,
+    target: f32,
+    smoothing: Smoothing,
+    time_ms: f32,
+    // How far a one pole moves towards the target each sample
+    coefficient: f32,
+    // The length of a linear ramp, in samples
+    ramp_length: usize,
+    // The step and remaining length of the current linear ramp
+    step: f32,
+    remaining: usize,
+}
+
+impl SmoothedParam {
+    pub fn new(value: f32, smoothing: Smoothing, time_ms: f32) -> SmoothedParam {
+        let mut param = SmoothedParam {
+            value,
+            target: value,
+            smoothing,
+            time_ms,
+            coefficient: 1.0,
+            ramp_length: 1,
+            step: 0.0,
+            remaining: 0,
+        };
+        param.set_sample_rate(44100.0);
+        param
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        let samples = self.time_ms / 1000.0 * sample_rate;
+        self.coefficient = if samples > 0.0 {
+            1.0 - (-1.0 / samples).exp()
+        } else {
+            1.0
+        };
+        self.ramp_length = (samples.round() as usize).max(1);
+    }
+
+    /// Start moving towards `target`.
+    pub fn set_target(&mut self, target: f32) {
+        #[allow(clippy::float_cmp)]
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        self.step = (target - self.value) / self.ramp_length as f32;
+        self.remaining = self.ramp_length;
+    }
+
+    /// Jump straight to `value`, such as when playback starts.
+    pub fn reset(&mut self, value: f32) {
+        self.value = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    /// Advance by one sample and return the new value.
+    pub fn advance(&mut self) -> f32 {
+        match self.smoothing {
+            Smoothing::OnePole => self.value += (self.target - self.value) * self.coefficient,
+            Smoothing::Linear => {
+                if self.remaining > 1 {
+                    self.value += self.step;
+                    self.remaining -= 1;
+                } else {
+                    self.value = self.target;
+                    self.remaining = 0;
+                }
+            }
+        }
+        self.value
+    }
+
+    /// The current value, without advancing.
+    pub fn get(&self) -> f32 {
+        self.value
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+}
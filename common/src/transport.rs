@@ -0,0 +1,111 @@
+use vst::{
+    api::{TimeInfo, TimeInfoFlags},
+    host::Host,
+    plugin::HostCallback,
+};
+
+/// The tempo to use when the host doesn't report one.
+pub const DEFAULT_TEMPO: f32 = 120.0;
+
+/// The host's tempo and playback position as of the start of a block. Anything
+/// the host doesn't report is filled in with a fallback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transport {
+    /// The tempo, in beats per minute, or `DEFAULT_TEMPO` if unknown
+    pub tempo: f32,
+    /// True while the host's transport is running
+    pub playing: bool,
+    /// The position of the first sample of the block, in quarter notes, or
+    /// None if the host doesn't report it
+    pub position: Option<f64>,
+    /// The position that the current bar started at, in quarter notes. If the
+    /// host doesn't report it, this is 0.0, which puts the bar lines at
+    /// multiples of the bar length.
+    pub bar_start: f64,
+    /// The time signature, as (numerator, denominator), or 4/4 if unknown
+    pub time_signature: (u32, u32),
+    pub sample_rate: f32,
+}
+
+impl Transport {
+    /// Ask the host for its current transport state.
+    pub fn read(host: &HostCallback, sample_rate: f32) -> Transport {
+        let mask = TimeInfoFlags::TEMPO_VALID
+            | TimeInfoFlags::PPQ_POS_VALID
+            | TimeInfoFlags::BARS_VALID
+            | TimeInfoFlags::TIME_SIG_VALID;
+        let time_info = host.get_time_info(mask.bits());
+        Transport::from_time_info(time_info.as_ref(), sample_rate)
+    }
+
+    /// The transport state described by `time_info`, or the fallbacks if the
+    /// host didn't give any.
+    pub fn from_time_info(time_info: Option<&TimeInfo>, sample_rate: f32) -> Transport {
+        let has_flag = |flag: TimeInfoFlags| {
+            time_info.is_some_and(|time_info| {
+                TimeInfoFlags::from_bits_truncate(time_info.flags).contains(flag)
+            })
+        };
+        // Only read fields whose flag is set, as the rest may be garbage.
+        let valid = |flag| time_info.filter(|_| has_flag(flag));
+        Transport {
+            tempo: valid(TimeInfoFlags::TEMPO_VALID)
+                .map(|time_info| time_info.tempo as f32)
+                .filter(|&tempo| tempo > 0.0)
+                .unwrap_or(DEFAULT_TEMPO),
+            playing: has_flag(TimeInfoFlags::TRANSPORT_PLAYING),
+            position: valid(TimeInfoFlags::PPQ_POS_VALID).map(|time_info| time_info.ppq_pos),
+            bar_start: valid(TimeInfoFlags::BARS_VALID)
+                .map_or(0.0, |time_info| time_info.bar_start_pos),
+            time_signature: valid(TimeInfoFlags::TIME_SIG_VALID).map_or((4, 4), |time_info| {
+                (
+                    time_info.time_sig_numerator.max(1) as u32,
+                    time_info.time_sig_denominator.max(1) as u32,
+                )
+            }),
+            sample_rate,
+        }
+    }
+
+    /// How many quarter notes each sample advances by.
+    pub fn quarters_per_sample(&self) -> f64 {
+        self.tempo as f64 / 60.0 / self.sample_rate as f64
+    }
+
+    /// The length of one beat of the time signature, in quarter notes.
+    pub fn beat_length(&self) -> f64 {
+        4.0 / self.time_signature.1 as f64
+    }
+
+    /// The length of one bar, in quarter notes.
+    pub fn bar_length(&self) -> f64 {
+        self.time_signature.0 as f64 * self.beat_length()
+    }
+
+    /// The position of the first sample of the block, in quarter notes, while
+    /// the transport is running. Returns None while stopped, or if the host
+    /// doesn't report its position.
+    pub fn playing_position(&self) -> Option<f64> {
+        self.position.filter(|_| self.playing)
+    }
+
+    /// The playing position measured in steps of `step` quarter notes, counting
+    /// from `origin`, and how many steps each sample advances by. The step
+    /// boundaries fall wherever the position is a whole number. Returns None
+    /// when `playing_position` does.
+    pub fn steps(&self, step: f64, origin: f64) -> Option<(f64, f64)> {
+        let position = self.playing_position()?;
+        Some((
+            (position - origin) / step,
+            self.quarters_per_sample() / step,
+        ))
+    }
+
+    /// The number of samples from the start of the block until the next bar
+    /// line, which is 0.0 if the block starts on one. Returns None when
+    /// `playing_position` does.
+    pub fn samples_to_next_bar(&self) -> Option<f64> {
+        let (bars, bars_per_sample) = self.steps(self.bar_length(), self.bar_start)?;
+        Some((bars.ceil() - bars) / bars_per_sample)
+    }
+}
@@ -0,0 +1,590 @@
+//! `#[derive(VstParameters)]`, which implements a plugin's parameters from
+//! the fields of its raw parameter struct. This only uses the compiler's own
+//! `proc_macro` API, and builds the generated code up as text.
+
+use std::iter::Peekable;
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+/// Implement a plugin's parameters from the fields of its raw parameter
+/// struct. The struct takes a `#[parameters(...)]` attribute with
+/// * `parameter_type`: the name of the enum to generate, which has one
+///   variant per parameter and their number as `COUNT`
+/// * `presets` (optional): a `&[Preset<parameter_type>]` listing the plugin's
+///   built-in programs
+///
+/// Each field with a `#[parameter(...)]` attribute is a parameter. These are
+/// numbered in the order they are declared, which hosts use to save
+/// automation, so new parameters should go after the existing ones. The
+/// attribute takes
+/// * `name`: the name the host shows
+/// * `default`: the default normalized value
+/// * `range` (optional): the parameter's `Range`, which is
+///   `Range::NORMALIZED` if not given
+/// * `strings` (optional): a closure which is called with the parameter's
+///   value in `Parameters` and the full `Parameters`, and returns the
+///   (value, unit) text the host shows. Parameters without one show their
+///   real value and unit.
+/// * `variant` (optional): the parameter's variant in `parameter_type`, if
+///   it isn't the field name in CamelCase
+///
+/// Parameters which also have a `#[smoothed]` attribute are included in the
+/// generated `SmoothedParameters`, which ramps them on the audio thread.
+///
+/// Fields with a `#[state(default = ...)]` attribute are saved along with
+/// the parameters, but aren't shown to the host. These hold state which
+/// isn't a setting, such as the result of a Learn pass.
+///
+/// The struct must also have the `current_preset`, `generation`, `pending`,
+/// `sample_rate` and `host` fields, which the generated `default` function
+/// sets up. A `tempo` field, for plugins which follow the host's tempo, is
+/// set up too if the struct has one.
+#[proc_macro_derive(VstParameters, attributes(parameters, parameter, smoothed, state))]
+pub fn derive_vst_parameters(input: TokenStream) -> TokenStream {
+    let code = match parse_struct(input) {
+        Ok(input) => generate(&input),
+        Err(message) => format!("compile_error!({:?});", message),
+    };
+    code.parse().expect("generated code should parse")
+}
+
+struct Input {
+    raw_parameters: String,
+    parameter_type: String,
+    presets: String,
+    parameters: Vec<Parameter>,
+    states: Vec<State>,
+    has_tempo: bool,
+}
+
+struct Parameter {
+    field_name: String,
+    variant: String,
+    name: String,
+    default: String,
+    range: String,
+    strings: Option<String>,
+    smoothed: bool,
+}
+
+struct State {
+    field_name: String,
+    default: String,
+}
+
+enum Field {
+    Parameter(Parameter),
+    State(State),
+    /// Any other field, with its name
+    Other(String),
+}
+
+fn parse_struct(input: TokenStream) -> Result<Input, String> {
+    let mut tokens = input.into_iter().peekable();
+    let mut args = None;
+    while is_punct(tokens.peek(), '#') {
+        tokens.next();
+        if let Some(found) = attribute_args(tokens.next(), "parameters")? {
+            args = Some(found);
+        }
+    }
+    skip_visibility(&mut tokens);
+
+    match tokens.next() {
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "struct" => (),
+        _ => return Err("VstParameters can only be derived for structs".to_string()),
+    }
+    let raw_parameters = match tokens.next() {
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        _ => return Err("expected the struct's name".to_string()),
+    };
+    let body = match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => group.stream(),
+        _ => {
+            return Err(
+                "VstParameters needs a struct with named fields and no generics".to_string(),
+            )
+        }
+    };
+
+    let args = args.ok_or("expected a #[parameters(parameter_type = ...)] attribute")?;
+    let mut parameter_type = None;
+    let mut presets = "&[]".to_string();
+    for (key, value) in args {
+        match key.as_str() {
+            "parameter_type" => parameter_type = Some(value),
+            "presets" => presets = value,
+            _ => return Err(format!("unknown #[parameters] argument `{}`", key)),
+        }
+    }
+
+    let mut parameters = vec![];
+    let mut states = vec![];
+    let mut has_tempo = false;
+    for field in split_commas(body) {
+        match parse_field(field)? {
+            Field::Parameter(parameter) => parameters.push(parameter),
+            Field::State(state) => states.push(state),
+            Field::Other(field_name) => has_tempo |= field_name == "tempo",
+        }
+    }
+
+    Ok(Input {
+        raw_parameters,
+        parameter_type: parameter_type.ok_or("expected a `parameter_type` argument")?,
+        presets,
+        parameters,
+        states,
+        has_tempo,
+    })
+}
+
+/// Parse a field, which is a parameter, state or some other field.
+fn parse_field(field: Vec<TokenTree>) -> Result<Field, String> {
+    let mut tokens = field.into_iter().peekable();
+    let mut args = None;
+    let mut state_args = None;
+    let mut smoothed = false;
+    while is_punct(tokens.peek(), '#') {
+        tokens.next();
+        let attribute = tokens.next();
+        if is_bare_attribute(attribute.as_ref(), "smoothed") {
+            smoothed = true;
+        } else if let Some(found) = attribute_args(attribute.clone(), "parameter")? {
+            args = Some(found);
+        } else if let Some(found) = attribute_args(attribute, "state")? {
+            state_args = Some(found);
+        }
+    }
+    skip_visibility(&mut tokens);
+
+    let field_name = match tokens.next() {
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        _ => return Err("expected a field name".to_string()),
+    };
+    let missing = |arg| format!("field `{}` needs a `{}` argument", field_name, arg);
+    if smoothed && args.is_none() {
+        return Err(format!(
+            "field `{}` is #[smoothed], but isn't a #[parameter]",
+            field_name
+        ));
+    }
+    if let Some(state_args) = state_args {
+        let mut default = None;
+        for (key, value) in state_args {
+            match key.as_str() {
+                "default" => default = Some(value),
+                _ => return Err(format!("unknown #[state] argument `{}`", key)),
+            }
+        }
+        return Ok(Field::State(State {
+            default: default.ok_or_else(|| missing("default"))?,
+            field_name,
+        }));
+    }
+    let args = match args {
+        Some(args) => args,
+        None => return Ok(Field::Other(field_name)),
+    };
+
+    let mut variant = camel_case(&field_name);
+    let mut name = None;
+    let mut default = None;
+    let mut range = "common::range::Range::NORMALIZED".to_string();
+    let mut strings = None;
+    for (key, value) in args {
+        match key.as_str() {
+            "variant" => variant = value,
+            "name" => name = Some(value),
+            "default" => default = Some(value),
+            "range" => range = value,
+            "strings" => strings = Some(value),
+            _ => return Err(format!("unknown #[parameter] argument `{}`", key)),
+        }
+    }
+    Ok(Field::Parameter(Parameter {
+        name: name.ok_or_else(|| missing("name"))?,
+        default: default.ok_or_else(|| missing("default"))?,
+        field_name,
+        variant,
+        range,
+        strings,
+        smoothed,
+    }))
+}
+
+/// True if `attribute` is `#[name]`, with no arguments.
+fn is_bare_attribute(attribute: Option<&TokenTree>, name: &str) -> bool {
+    match attribute {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => {
+            let mut tokens = group.stream().into_iter();
+            matches!(tokens.next(), Some(TokenTree::Ident(ident)) if ident.to_string() == name)
+                && tokens.next().is_none()
+        }
+        _ => false,
+    }
+}
+
+/// The `key = value` arguments of `attribute` if it is `#[name(...)]`, or
+/// None if it is some other attribute, such as a doc comment.
+fn attribute_args(
+    attribute: Option<TokenTree>,
+    name: &str,
+) -> Result<Option<Vec<(String, String)>>, String> {
+    let attribute = match attribute {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => group,
+        _ => return Err("expected an attribute".to_string()),
+    };
+    let mut tokens = attribute.stream().into_iter();
+    match tokens.next() {
+        Some(TokenTree::Ident(ident)) if ident.to_string() == name => (),
+        _ => return Ok(None),
+    }
+    let args = match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => group,
+        _ => return Err(format!("expected #[{}(...)]", name)),
+    };
+
+    split_commas(args.stream())
+        .into_iter()
+        .map(|arg| {
+            let mut tokens = arg.into_iter();
+            match (tokens.next(), tokens.next()) {
+                (Some(TokenTree::Ident(key)), Some(TokenTree::Punct(punct)))
+                    if punct.as_char() == '=' =>
+                {
+                    let value: TokenStream = tokens.collect();
+                    if value.is_empty() {
+                        Err(format!("`{}` needs a value", key))
+                    } else {
+                        Ok((key.to_string(), value.to_string()))
+                    }
+                }
+                _ => Err(format!("expected arguments like #[{}(key = value)]", name)),
+            }
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Split `stream` at its top level commas. A closure's arguments aren't
+/// grouped like other brackets, so commas between the pipes of a closure
+/// given as an argument's value are kept.
+fn split_commas(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut segments = vec![vec![]];
+    let mut in_closure_args = false;
+    for token in stream {
+        let segment = segments.last_mut().unwrap();
+        if let TokenTree::Punct(punct) = &token {
+            if punct.as_char() == '|' && (in_closure_args || is_punct(segment.last(), '=')) {
+                in_closure_args = !in_closure_args;
+            } else if punct.as_char() == ',' && !in_closure_args {
+                segments.push(vec![]);
+                continue;
+            }
+        }
+        segment.push(token);
+    }
+    segments.retain(|segment| !segment.is_empty());
+    segments
+}
+
+fn skip_visibility(tokens: &mut Peekable<impl Iterator<Item = TokenTree>>) {
+    if let Some(TokenTree::Ident(ident)) = tokens.peek() {
+        if ident.to_string() == "pub" {
+            tokens.next();
+            // pub(crate) and the like
+            if let Some(TokenTree::Group(group)) = tokens.peek() {
+                if group.delimiter() == Delimiter::Parenthesis {
+                    tokens.next();
+                }
+            }
+        }
+    }
+}
+
+fn is_punct(token: Option<&TokenTree>, c: char) -> bool {
+    matches!(token, Some(TokenTree::Punct(punct)) if punct.as_char() == c)
+}
+
+fn camel_case(field_name: &str) -> String {
+    field_name
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn generate(input: &Input) -> String {
+    let raw = &input.raw_parameters;
+    let ty = &input.parameter_type;
+    // Apply `f` to each parameter and its index, and join the results.
+    let each = |f: &dyn Fn(usize, &Parameter) -> String| -> String {
+        input
+            .parameters
+            .iter()
+            .enumerate()
+            .map(|(idx, parameter)| f(idx, parameter))
+            .collect()
+    };
+    let each_state =
+        |f: &dyn Fn(&State) -> String| -> String { input.states.iter().map(f).collect() };
+    let has_strings = input.parameters.iter().any(|p| p.strings.is_some());
+
+    format!(
+        r#"
+common::impl_plugin_parameters! {{{raw}, {ty}, {presets}}}
+common::impl_get_set! {{{raw}, {ty}}}
+
+/// The list of parameters that exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum {ty} {{
+    {variants}
+}}
+
+impl std::convert::TryFrom<i32> for {ty} {{
+    type Error = ();
+    fn try_from(x: i32) -> Result<Self, Self::Error> {{
+        match x {{
+            {try_from}
+            _ => Err(()),
+        }}
+    }}
+}}
+
+impl std::convert::From<{ty}> for i32 {{
+    fn from(x: {ty}) -> i32 {{
+        match x {{
+            {into}
+        }}
+    }}
+}}
+
+impl std::fmt::Display for {ty} {{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{
+        match self {{
+            {display}
+        }}
+    }}
+}}
+
+impl {ty} {{
+    /// The number of parameters.
+    pub const COUNT: usize = {count};
+
+    /// The real values this parameter covers.
+    pub fn range(&self) -> common::range::Range {{
+        match self {{
+            {range}
+        }}
+    }}
+}}
+
+impl {raw} {{
+    fn get_ref(&self, x: {ty}) -> &vst::util::AtomicFloat {{
+        match x {{
+            {get_ref}
+        }}
+    }}
+
+    fn get_default(x: {ty}) -> f32 {{
+        match x {{
+            {get_default}
+        }}
+    }}
+
+    fn default(host: vst::plugin::HostCallback) -> Self {{
+        {raw} {{
+            {default}
+            {state_default}
+            current_preset: std::sync::atomic::AtomicI32::new(0),
+            generation: std::sync::atomic::AtomicUsize::new(0),
+            pending: common::gesture::PendingEdits::new({count}),
+            sample_rate: vst::util::AtomicFloat::new(44100.0),
+            {tempo}
+            host,
+        }}
+    }}
+
+    /// Returns a user-facing text output for the given parameter. This is
+    /// broken into a tuple consisting of (`value`, `units`).
+    fn get_strings(&self, parameter: {ty}) -> (String, String) {{
+        {params}
+        match parameter {{
+            {strings}
+        }}
+    }}
+
+    /// The current value of `parameter`, converted to its range.
+    pub fn real(&self, parameter: {ty}) -> f32 {{
+        parameter.range().to_real(self.get(parameter))
+    }}
+
+    /// Save every parameter's normalized value, along with the state.
+    fn to_chunk(&self) -> common::chunk::Chunk {{
+        let mut parameters = std::collections::BTreeMap::new();
+        {to_chunk}
+        let mut state = std::collections::BTreeMap::new();
+        {state_to_chunk}
+        common::chunk::Chunk::new(parameters, state)
+    }}
+
+    /// Restore the values saved in `chunk`. Parameters and state it doesn't
+    /// have are reset to their defaults.
+    fn load_chunk(&self, chunk: &common::chunk::Chunk) {{
+        {load_chunk}
+        {state_load_chunk}
+        self.mark_changed();
+    }}
+}}
+
+{smoothed}
+"#,
+        raw = raw,
+        ty = ty,
+        presets = input.presets,
+        count = input.parameters.len(),
+        tempo = if input.has_tempo {
+            "tempo: vst::util::AtomicFloat::new(0.0),"
+        } else {
+            ""
+        },
+        variants = each(&|_, p| format!("{},", p.variant)),
+        try_from = each(&|idx, p| format!("{} => Ok({}::{}),", idx, ty, p.variant)),
+        into = each(&|idx, p| format!("{}::{} => {},", ty, p.variant, idx)),
+        display = each(&|_, p| format!("{}::{} => f.write_str({}),", ty, p.variant, p.name)),
+        range = each(&|_, p| format!("{}::{} => {},", ty, p.variant, p.range)),
+        get_ref = each(&|_, p| format!("{}::{} => &self.{},", ty, p.variant, p.field_name)),
+        get_default = each(&|_, p| format!("{}::{} => {},", ty, p.variant, p.default)),
+        default = each(&|_, p| format!(
+            "{}: vst::util::AtomicFloat::new({}),",
+            p.field_name, p.default
+        )),
+        state_default = each_state(&|s| format!(
+            "{}: vst::util::AtomicFloat::new({}),",
+            s.field_name, s.default
+        )),
+        params = if has_strings {
+            "let params = Parameters::from(self);"
+        } else {
+            ""
+        },
+        strings = each(&|_, p| match &p.strings {
+            Some(strings) => format!(
+                "{}::{} => ({})(params.{}, &params),",
+                ty, p.variant, strings, p.field_name
+            ),
+            None => format!(
+                "{}::{} => parameter.range().strings(self.real(parameter)),",
+                ty, p.variant
+            ),
+        }),
+        to_chunk = each(&|_, p| format!(
+            "parameters.insert({:?}.to_string(), self.{}.get());",
+            p.field_name, p.field_name
+        )),
+        load_chunk = each(&|_, p| format!(
+            "self.{}.set(chunk.get({:?}).unwrap_or({}));",
+            p.field_name, p.field_name, p.default
+        )),
+        state_to_chunk = each_state(&|s| format!(
+            "state.insert({:?}.to_string(), self.{}.get());",
+            s.field_name, s.field_name
+        )),
+        state_load_chunk = each_state(&|s| format!(
+            "self.{}.set(chunk.get_state({:?}).unwrap_or({}));",
+            s.field_name, s.field_name, s.default
+        )),
+        smoothed = generate_smoothed(input),
+    )
+}
+
+/// Generate `SmoothedParameters`, which holds the `#[smoothed]` parameters, or
+/// nothing if there are none.
+fn generate_smoothed(input: &Input) -> String {
+    let raw = &input.raw_parameters;
+    let ty = &input.parameter_type;
+    let smoothed: Vec<&Parameter> = input.parameters.iter().filter(|p| p.smoothed).collect();
+    if smoothed.is_empty() {
+        return String::new();
+    }
+    let each = |f: &dyn Fn(&Parameter) -> String| -> String {
+        smoothed.iter().map(|parameter| f(parameter)).collect()
+    };
+    let others = if smoothed.len() < input.parameters.len() {
+        "_ => panic!(\"{} isn't smoothed\", parameter),".to_string()
+    } else {
+        String::new()
+    };
+
+    format!(
+        r#"
+/// The raw values of the `#[smoothed]` parameters, smoothed on the audio
+/// thread so that automation doesn't cause zipper noise. Like the raw values,
+/// these are in the [0.0, 1.0] range.
+pub struct SmoothedParameters {{
+    {smoothed_fields}
+}}
+
+impl SmoothedParameters {{
+    pub fn new(params: &{raw}, smoothing: common::smooth::Smoothing, time_ms: f32) -> Self {{
+        let mut smoothed = SmoothedParameters {{
+            {smoothed_new}
+        }};
+        smoothed.set_sample_rate(params.sample_rate.get());
+        smoothed
+    }}
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {{
+        {smoothed_sample_rate}
+    }}
+
+    /// Start moving towards the current raw values. This should be called
+    /// once per block.
+    pub fn update(&mut self, params: &{raw}) {{
+        {smoothed_update}
+    }}
+
+    /// Advance every smoothed parameter by one sample.
+    pub fn advance(&mut self) {{
+        {smoothed_advance}
+    }}
+
+    /// The smoothed normalized value of `parameter`. This panics if
+    /// `parameter` isn't `#[smoothed]`.
+    pub fn get(&self, parameter: {ty}) -> f32 {{
+        match parameter {{
+            {smoothed_get}
+            {others}
+        }}
+    }}
+
+    /// The smoothed value of `parameter`, converted to its range.
+    pub fn real(&self, parameter: {ty}) -> f32 {{
+        parameter.range().to_real(self.get(parameter))
+    }}
+}}
+"#,
+        raw = raw,
+        ty = ty,
+        others = others,
+        smoothed_fields = each(&|p| format!("{}: common::smooth::SmoothedParam,", p.field_name)),
+        smoothed_new = each(&|p| format!(
+            "{}: common::smooth::SmoothedParam::new(params.{}.get(), smoothing, time_ms),",
+            p.field_name, p.field_name
+        )),
+        smoothed_sample_rate =
+            each(&|p| format!("self.{}.set_sample_rate(sample_rate);", p.field_name)),
+        smoothed_update = each(&|p| format!(
+            "self.{}.set_target(params.{}.get());",
+            p.field_name, p.field_name
+        )),
+        smoothed_advance = each(&|p| format!("self.{}.advance();", p.field_name)),
+        smoothed_get = each(&|p| format!("{}::{} => self.{}.get(),", ty, p.variant, p.field_name)),
+    )
+}
@@ -0,0 +1,140 @@
+use common::flush_denormal;
+
+/// Returns the coefficient for a one-pole filter which takes `time_ms` to
+/// reach about 63% of the way to its target.
+pub fn one_pole_coef(time_ms: f32, sample_rate: f32) -> f32 {
+    let samples = time_ms / 1000.0 * sample_rate;
+    if samples <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / samples).exp()
+    }
+}
+
+/// A simple peak envelope follower.
+pub struct Envelope {
+    value: f32,
+    coef: f32,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            value: 0.0,
+            coef: 0.0,
+        }
+    }
+
+    pub fn set_time(&mut self, time_ms: f32, sample_rate: f32) {
+        self.coef = one_pole_coef(time_ms, sample_rate);
+    }
+
+    pub fn next(&mut self, input: f32) -> f32 {
+        self.value = flush_denormal(self.coef * self.value + (1.0 - self.coef) * input.abs());
+        self.value
+    }
+}
+
+/// The envelope level under which no onsets are detected, so that noise in
+/// near-silence doesn't trigger.
+const ONSET_FLOOR: f32 = 1.0e-4;
+
+/// Detects transients by comparing a fast envelope against a slow envelope.
+/// When the fast envelope jumps above the slow one by more than a ratio set
+/// by the sensitivity, the detector outputs 1.0, then decays back to 0.0 over
+/// the recovery time.
+pub struct TransientDetector {
+    fast: Envelope,
+    slow: Envelope,
+    ratio: f32,
+    recovery_coef: f32,
+    amount: f32,
+}
+
+impl TransientDetector {
+    pub fn new() -> TransientDetector {
+        TransientDetector {
+            fast: Envelope::new(),
+            slow: Envelope::new(),
+            ratio: f32::INFINITY,
+            recovery_coef: 0.0,
+            amount: 0.0,
+        }
+    }
+
+    /// Set the detector's parameters. A `sensitivity` of 0.0 disables the
+    /// detector entirely.
+    pub fn set_params(&mut self, sensitivity: f32, recovery_ms: f32, sample_rate: f32) {
+        self.fast.set_time(1.0, sample_rate);
+        self.slow.set_time(40.0, sample_rate);
+        self.ratio = if sensitivity <= 0.0 {
+            f32::INFINITY
+        } else {
+            1.5 + (1.0 - sensitivity) * 8.5
+        };
+        self.recovery_coef = one_pole_coef(recovery_ms, sample_rate);
+    }
+
+    /// Returns how strongly a transient is currently occurring, from 0.0 to 1.0
+    pub fn next(&mut self, input: f32) -> f32 {
+        let fast = self.fast.next(input);
+        let slow = self.slow.next(input);
+        if fast > ONSET_FLOOR && fast > slow * self.ratio {
+            self.amount = 1.0;
+        } else {
+            self.amount *= self.recovery_coef;
+        }
+        self.amount
+    }
+}
+
+/// The distance, in decibels, between the gate's opening and closing levels.
+const GATE_HYSTERESIS_DB: f32 = 3.0;
+
+/// A gate which decides whether the median path is engaged based on the input
+/// level. The gate switches with hysteresis so levels hovering around the
+/// threshold don't cause chatter, and its output is smoothed so that switching
+/// doesn't click.
+pub struct Gate {
+    envelope: Envelope,
+    engaged: bool,
+    amount: f32,
+    smoothing_coef: f32,
+}
+
+impl Gate {
+    pub fn new() -> Gate {
+        Gate {
+            envelope: Envelope::new(),
+            engaged: true,
+            amount: 1.0,
+            smoothing_coef: 0.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.envelope.set_time(10.0, sample_rate);
+        self.smoothing_coef = one_pole_coef(5.0, sample_rate);
+    }
+
+    /// Returns how engaged the median path should be, from 0.0 to 1.0. If
+    /// `below` is true, the gate engages when the input is quieter than the
+    /// threshold, otherwise it engages when the input is louder.
+    pub fn next(&mut self, input: f32, threshold_db: f32, below: bool) -> f32 {
+        let level_db = 20.0 * self.envelope.next(input).max(1.0e-10).log10();
+        let low = threshold_db - GATE_HYSTERESIS_DB / 2.0;
+        let high = threshold_db + GATE_HYSTERESIS_DB / 2.0;
+
+        if level_db < low {
+            self.engaged = below;
+        } else if level_db > high {
+            self.engaged = !below;
+        }
+
+        let target = if self.engaged { 1.0 } else { 0.0 };
+        self.amount = flush_denormal(
+            self.smoothing_coef * self.amount + (1.0 - self.smoothing_coef) * target,
+        );
+        self.amount
+    }
+}
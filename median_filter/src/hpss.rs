@@ -0,0 +1,80 @@
+use crate::{
+    running_median::median,
+    spectral::{Stft, FRAME_SIZE, NUM_BINS},
+};
+
+/// The number of past frames the harmonic (time-direction) median looks at.
+const TIME_WINDOW: usize = 17;
+/// The number of neighboring bins the percussive (frequency-direction) median
+/// looks at.
+const FREQ_WINDOW: usize = 17;
+
+/// Harmonic/percussive source separation. Harmonic content is steady across
+/// time, so a median along time keeps it and rejects transients. Percussive
+/// content is broadband, so a median along frequency keeps it and rejects
+/// tonal peaks. The two medians are turned into soft masks which are mixed
+/// according to the balance.
+pub struct Hpss {
+    stft: Stft,
+    // The magnitude spectra of the last TIME_WINDOW frames, as a ring buffer.
+    history: Vec<[f32; NUM_BINS]>,
+    history_pos: usize,
+    scratch: Vec<f32>,
+}
+
+impl Hpss {
+    pub fn new() -> Hpss {
+        Hpss {
+            stft: Stft::new(),
+            history: vec![[0.0; NUM_BINS]; TIME_WINDOW],
+            history_pos: 0,
+            scratch: Vec::with_capacity(TIME_WINDOW.max(FREQ_WINDOW)),
+        }
+    }
+
+    /// Process one sample. A `balance` of 0.0 outputs only the harmonic part,
+    /// 1.0 outputs only the percussive part, and 0.5 outputs both.
+    pub fn next(&mut self, input: f32, balance: f32) -> f32 {
+        let harmonic_gain = (2.0 * (1.0 - balance)).min(1.0);
+        let percussive_gain = (2.0 * balance).min(1.0);
+
+        let history = &mut self.history;
+        let history_pos = &mut self.history_pos;
+        let scratch = &mut self.scratch;
+        self.stft.process(input, |spectrum| {
+            let magnitudes = &mut history[*history_pos];
+            for (mag, bin) in magnitudes.iter_mut().zip(spectrum.iter()) {
+                *mag = bin.norm();
+            }
+            let magnitudes = *magnitudes;
+            *history_pos = (*history_pos + 1) % TIME_WINDOW;
+
+            for k in 0..NUM_BINS {
+                scratch.clear();
+                scratch.extend(history.iter().map(|frame| frame[k]));
+                let harmonic = median(scratch);
+
+                let lo = k.saturating_sub(FREQ_WINDOW / 2);
+                let hi = (k + FREQ_WINDOW / 2 + 1).min(NUM_BINS);
+                scratch.clear();
+                scratch.extend_from_slice(&magnitudes[lo..hi]);
+                let percussive = median(scratch);
+
+                // Wiener-style soft masks. These always sum to 1.0.
+                let h2 = harmonic * harmonic;
+                let p2 = percussive * percussive;
+                let gain = if h2 + p2 > 0.0 {
+                    (harmonic_gain * h2 + percussive_gain * p2) / (h2 + p2)
+                } else {
+                    0.0
+                };
+
+                spectrum[k] *= gain;
+                // Keep the spectrum conjugate-symmetric so the output stays real.
+                if k != 0 && k != FRAME_SIZE / 2 {
+                    spectrum[FRAME_SIZE - k] *= gain;
+                }
+            }
+        })
+    }
+}
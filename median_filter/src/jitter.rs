@@ -0,0 +1,46 @@
+use common::rng::Rng;
+
+/// A smoothly wandering random value in the range [-1.0, 1.0]. Every `period`
+/// samples, a new random target is picked, and the value glides linearly
+/// towards it over the next period.
+pub struct Jitter {
+    rng: Rng,
+    seed: u32,
+    value: f32,
+    step: f32,
+    // The number of samples until a new target is picked.
+    remaining: usize,
+}
+
+impl Jitter {
+    pub fn new(seed: u32) -> Jitter {
+        Jitter {
+            rng: Rng::new(seed),
+            seed,
+            value: 0.0,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    /// Restart the random sequence from `seed`.
+    pub fn reseed(&mut self, seed: u32) {
+        *self = Jitter::new(seed);
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn next(&mut self, period: usize) -> f32 {
+        if self.remaining == 0 {
+            let period = period.max(1);
+            let target = self.rng.next_bipolar();
+            self.step = (target - self.value) / period as f32;
+            self.remaining = period;
+        }
+        self.remaining -= 1;
+        self.value += self.step;
+        self.value
+    }
+}
@@ -0,0 +1,202 @@
+use crate::running_median::RunningMedian;
+
+/// How long a Learn pass listens to the input, in seconds.
+const LEARN_SECONDS: f32 = 2.0;
+/// The size of the median used as a spike-free reference for the input. Spikes
+/// wider than half of this aren't detected.
+const REFERENCE_WINDOW: usize = 33;
+/// The widest spike, in samples, that can be detected.
+const MAX_SPIKE_WIDTH: usize = REFERENCE_WINDOW / 2;
+/// The lowest residual level, in decibels, that is told apart from silence.
+const MIN_DB: f32 = -120.0;
+/// The residual levels are counted in 1 dB bins from `MIN_DB` to 0 dB.
+const NUM_LEVELS: usize = 121;
+/// The percentile of residual levels taken as the noise floor.
+const NOISE_PERCENTILE: f32 = 0.5;
+/// The percentile of residual levels taken as the typical spike level.
+const SPIKE_PERCENTILE: f32 = 0.999;
+/// The percentile of spike widths the suggested window should remove.
+const WIDTH_PERCENTILE: f32 = 0.95;
+/// The smallest distance, in decibels, between the threshold and the noise
+/// floor, so that noise is never mistaken for clicks.
+const MIN_MARGIN_DB: f32 = 6.0;
+
+/// The result of a Learn pass.
+pub struct Profile {
+    /// The deviation, in decibels, above which a sample is considered a click.
+    pub threshold_db: f32,
+    /// The width, in samples, of the widest typical click.
+    pub spike_width: usize,
+}
+
+/// Analyzes a couple of seconds of input to find the noise floor and how loud
+/// and wide the clicks in it are. Each sample's residual (its distance from a
+/// median of its neighbors) is small for noise and large for clicks, so the
+/// bulk of the residual distribution gives the noise floor and its upper tail
+/// gives the click level.
+pub struct Learner {
+    left: LearnChannel,
+    right: LearnChannel,
+    levels: [u32; NUM_LEVELS],
+    // The number of samples a full pass analyzes.
+    length: usize,
+    // The number of samples left in the current pass, or 0 if not learning.
+    remaining: usize,
+}
+
+impl Learner {
+    pub fn new() -> Learner {
+        Learner {
+            left: LearnChannel::new(),
+            right: LearnChannel::new(),
+            levels: [0; NUM_LEVELS],
+            length: 0,
+            remaining: 0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.length = (LEARN_SECONDS * sample_rate) as usize;
+        // Allocate up front so that nothing is allocated on the audio thread.
+        self.left.reserve(self.length);
+        self.right.reserve(self.length);
+    }
+
+    pub fn is_learning(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// Start a new pass, discarding any pass in progress.
+    pub fn start(&mut self) {
+        self.left.clear();
+        self.right.clear();
+        self.levels = [0; NUM_LEVELS];
+        // Give the reference median time to fill up before analyzing.
+        self.remaining = self.length + REFERENCE_WINDOW;
+    }
+
+    /// Analyze a sample from each channel. Returns the learned profile once
+    /// the pass is over.
+    pub fn next(&mut self, left: f32, right: f32) -> Option<Profile> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let analyze = self.remaining <= self.length;
+        let levels = [
+            self.left.next(left, analyze),
+            self.right.next(right, analyze),
+        ];
+        for level in levels.iter().flatten() {
+            self.levels[level_index(*level)] += 1;
+        }
+        self.remaining -= 1;
+
+        if self.remaining == 0 {
+            Some(self.profile())
+        } else {
+            None
+        }
+    }
+
+    fn profile(&self) -> Profile {
+        let noise_floor_db = self.percentile(NOISE_PERCENTILE);
+        let spike_db = self.percentile(SPIKE_PERCENTILE);
+        // Put the threshold halfway between the noise and the clicks.
+        let threshold_db = ((noise_floor_db + spike_db) / 2.0).max(noise_floor_db + MIN_MARGIN_DB);
+
+        let mut widths = [0; MAX_SPIKE_WIDTH + 1];
+        self.left.count_spikes(&mut widths, threshold_db);
+        self.right.count_spikes(&mut widths, threshold_db);
+        let total = widths.iter().sum::<u32>();
+        let spike_width = if total == 0 {
+            1
+        } else {
+            percentile_index(&widths, total, WIDTH_PERCENTILE).max(1)
+        };
+
+        Profile {
+            threshold_db,
+            spike_width,
+        }
+    }
+
+    /// Returns the residual level, in decibels, below which `p` of the analyzed
+    /// samples fall.
+    fn percentile(&self, p: f32) -> f32 {
+        let total = self.levels.iter().sum::<u32>();
+        MIN_DB + percentile_index(&self.levels, total, p) as f32
+    }
+}
+
+/// Returns the index of the bin in which the running total of `counts` first
+/// reaches `p` of `total`.
+fn percentile_index(counts: &[u32], total: u32, p: f32) -> usize {
+    let target = (total as f32 * p).ceil() as u32;
+    let mut sum = 0;
+    for (i, count) in counts.iter().enumerate() {
+        sum += count;
+        if sum >= target {
+            return i;
+        }
+    }
+    counts.len() - 1
+}
+
+fn level_index(level_db: f32) -> usize {
+    ((level_db - MIN_DB).round().max(0.0) as usize).min(NUM_LEVELS - 1)
+}
+
+struct LearnChannel {
+    reference: RunningMedian,
+    // The residual level of every analyzed sample, in decibels
+    residuals: Vec<f32>,
+}
+
+impl LearnChannel {
+    fn new() -> LearnChannel {
+        LearnChannel {
+            reference: RunningMedian::new(REFERENCE_WINDOW),
+            residuals: Vec::new(),
+        }
+    }
+
+    fn reserve(&mut self, length: usize) {
+        self.residuals
+            .reserve(length.saturating_sub(self.residuals.len()));
+    }
+
+    fn clear(&mut self) {
+        self.residuals.clear();
+    }
+
+    /// Push a sample and, if `analyze` is true, return the residual level of
+    /// the sample in the middle of the reference window.
+    fn next(&mut self, input: f32, analyze: bool) -> Option<f32> {
+        self.reference.push(input);
+        if !analyze {
+            return None;
+        }
+        let median = self.reference.median(REFERENCE_WINDOW, 0);
+        let center = self.reference.get(REFERENCE_WINDOW / 2);
+        let level = 20.0 * (center - median).abs().max(1.0e-10).log10();
+        if self.residuals.len() < self.residuals.capacity() {
+            self.residuals.push(level);
+        }
+        Some(level)
+    }
+
+    /// Count the runs of consecutive samples louder than `threshold_db` by
+    /// their length.
+    fn count_spikes(&self, widths: &mut [u32], threshold_db: f32) {
+        let mut run = 0;
+        for &level in self.residuals.iter().chain(std::iter::once(&MIN_DB)) {
+            if level > threshold_db {
+                run += 1;
+            } else if run > 0 {
+                widths[run.min(widths.len() - 1)] += 1;
+                run = 0;
+            }
+        }
+    }
+}
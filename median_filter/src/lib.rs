@@ -1,9 +1,18 @@
-#[macro_use]
-extern crate common;
+mod envelope;
+mod hpss;
+mod jitter;
+mod learn;
+mod running_median;
+mod spectral;
 
-use std::sync::Arc;
+use std::{
+    f32::consts::FRAC_1_SQRT_2,
+    sync::{
+        atomic::{AtomicI32, AtomicUsize},
+        Arc,
+    },
+};
 
-use median::heap::Filter;
 use vst::{
     api::Supported,
     buffer::AudioBuffer,
@@ -12,28 +21,107 @@ use vst::{
     util::AtomicFloat,
 };
 
-use common::make_strings;
+use common::{
+    db_to_gain,
+    delay::Delay,
+    filter::Biquad,
+    flush_denormal,
+    gesture::PendingEdits,
+    initial_delay, make_strings,
+    range::{choice_label, Range},
+    smooth::Smoothing,
+    Automatable, Preset, VstParameters,
+};
+
+use envelope::{Gate, TransientDetector};
+use hpss::Hpss;
+use jitter::Jitter;
+use learn::{Learner, Profile};
+use running_median::StereoMedian;
+
+/// The largest window, in samples, that the Window Size parameter can select.
+const MAX_WINDOW_SIZE: usize = 100;
+/// The lookahead, in samples, used when the window is centered. This is fixed
+/// so that the latency doesn't change as the window size does.
+const CENTERED_LATENCY: usize = (MAX_WINDOW_SIZE - 1) / 2;
+/// The length of the crossfade when bypassing, in milliseconds.
+const BYPASS_FADE_MS: f32 = 10.0;
+/// How long the window size and wet/dry take to follow automation, in
+/// milliseconds.
+const SMOOTHING_MS: f32 = 20.0;
+/// The mu-law compression constant, as used in telephony.
+const MU: f32 = 255.0;
+/// The lowest de-click threshold, in decibels.
+const MIN_THRESHOLD_DB: f32 = -80.0;
+/// The largest factor the Downsample parameter can select.
+const MAX_DOWNSAMPLE: usize = 16;
+/// The number of factors the Downsample parameter can select, from 1x up to
+/// `MAX_DOWNSAMPLE` in powers of two.
+const DOWNSAMPLE_FACTORS: usize = MAX_DOWNSAMPLE.trailing_zeros() as usize + 1;
+/// The longest delay, in samples, the wet path can introduce. This must be at
+/// least `CENTERED_LATENCY * MAX_DOWNSAMPLE`.
+const MAX_LATENCY: usize = spectral::FRAME_SIZE;
 
 struct MedianFilter {
     params: Arc<RawParameters>,
-    left_filter: Filter<f32>,
-    right_filter: Filter<f32>,
-    last_window_size: usize,
+    median: StereoMedian,
+    hold: Hold,
+    // The number of samples since a sample was last pushed to the median, when
+    // downsampling.
+    decimation_phase: usize,
+    left: Channel,
+    right: Channel,
+    jitter: Jitter,
+    learner: Learner,
+    // The window size and wet/dry follow these, so that they ramp to new values
+    // instead of jumping.
+    smoothed: SmoothedParameters,
+    // The parameters as of `generation`, rebuilt only when it moves. The
+    // generation starts as None so that the first block sets everything up.
+    parameters: Parameters,
+    generation: Option<usize>,
 }
 
 impl Plugin for MedianFilter {
     fn new(host: HostCallback) -> Self {
+        let params = RawParameters::default(host);
+        let smoothed = SmoothedParameters::new(&params, Smoothing::Linear, SMOOTHING_MS);
+        let parameters = Parameters::from(&params);
         MedianFilter {
-            params: Arc::new(RawParameters::default(host)),
-            left_filter: Filter::new(50),
-            right_filter: Filter::new(50),
-            last_window_size: 50,
+            params: Arc::new(params),
+            median: StereoMedian::new(MAX_WINDOW_SIZE + CENTERED_LATENCY),
+            hold: Hold::new(),
+            decimation_phase: 0,
+            left: Channel::new(),
+            right: Channel::new(),
+            jitter: Jitter::new(0),
+            learner: Learner::new(),
+            smoothed,
+            parameters,
+            generation: None,
         }
     }
 
     fn init(&mut self) {
         let params = Parameters::from(self.params.as_ref());
-        self.last_window_size = params.window_size;
+        self.smoothed = SmoothedParameters::new(&self.params, Smoothing::Linear, SMOOTHING_MS);
+        self.left.set_sample_rate(params.sample_rate);
+        self.right.set_sample_rate(params.sample_rate);
+        self.learner.set_sample_rate(params.sample_rate);
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.params.sample_rate.set(rate);
+        self.params.mark_changed();
+        self.left.set_sample_rate(rate);
+        self.right.set_sample_rate(rate);
+        self.learner.set_sample_rate(rate);
+        self.smoothed.set_sample_rate(rate);
+    }
+
+    fn resume(&mut self) {
+        // Restart the jitter so that renders are reproducible.
+        self.jitter.reseed(self.jitter.seed());
     }
 
     fn get_info(&self) -> Info {
@@ -47,54 +135,88 @@ impl Plugin for MedianFilter {
             category: Category::Effect,
             // Subtract one here due to "error" type
             parameters: ParameterType::COUNT as i32,
+            presets: PRESETS.len() as i32,
             // Two audio inputs
             inputs: 2,
             // Two channel audio!
             outputs: 2,
+            // Save the state as a chunk, so that it survives parameters being
+            // added or reordered.
+            preset_chunks: true,
+            initial_delay: initial_delay(self.latency()),
             // For now, fill in the rest of our fields with `Default` info.
             ..Default::default()
         }
     }
 
-    fn can_do(&self, can_do: CanDo) -> Supported {
-        match can_do {
-            CanDo::Bypass => Supported::Yes,
-            _ => Supported::No,
-        }
+    // The vst crate doesn't pass the host's bypass state on to us, so bypass
+    // isn't advertised and the host handles its own. The Bypass parameter
+    // does a click-free soft bypass instead.
+    fn can_do(&self, _can_do: CanDo) -> Supported {
+        Supported::No
+    }
+
+    fn get_tail_size(&self) -> isize {
+        // After the input stops, the wet signal keeps going until the last
+        // input sample has left both the latency buffer and the window.
+        let params = Parameters::from(self.params.as_ref());
+        (params.latency() + params.window_size * params.downsample) as isize
     }
 
     // Output audio given the current state of the VST
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        self.reset_if_changed();
-        let params = Parameters::from(self.params.as_ref());
-        let wet_dry = params.wet_dry;
-        let num_samples = buffer.samples();
+        if self.update_params() {
+            self.left.set_params(&self.parameters);
+            self.right.set_params(&self.parameters);
+        }
+        let params = self.parameters;
+        if params.jitter_seed != self.jitter.seed() {
+            self.jitter.reseed(params.jitter_seed);
+        }
+        if params.learn && !self.learner.is_learning() {
+            self.learner.start();
+        }
+        self.smoothed.update(&self.params);
 
+        let num_samples = buffer.samples();
         let (inputs, mut outputs) = buffer.split();
-        let left_input = &inputs[0];
-        let left_output = &mut outputs[0];
 
         for i in 0..num_samples {
-            self.left_filter.consume(left_input[i]);
-            let out = if self.left_filter.is_empty() != 0 {
-                self.left_filter.median()
-            } else {
-                0.0
-            };
-            left_output[i] = left_input[i] * (1.0 - wet_dry) + out * wet_dry;
-        }
+            self.smoothed.advance();
+            let wet_dry = self.smoothed.get(ParameterType::WetDry);
 
-        let right_input = &inputs[1];
-        let right_output = &mut outputs[1];
+            let window_size =
+                (self.smoothed.get(ParameterType::WindowSize) * MAX_WINDOW_SIZE as f32).max(1.0);
+            // The jitter is shared by both channels so that the stereo image
+            // doesn't wobble.
+            let jitter = self.jitter.next(params.jitter_rate);
+            let window_size = window_size + jitter * params.jitter * MAX_WINDOW_SIZE as f32 / 2.0;
+            let window_size = (window_size.round() as usize).clamp(1, MAX_WINDOW_SIZE);
+            // When centered, line the middle of the window up with the fixed
+            // lookahead.
+            let offset = match params.alignment {
+                Alignment::Causal => 0,
+                Alignment::Centered => CENTERED_LATENCY - (window_size - 1) / 2,
+            };
 
-        for i in 0..num_samples {
-            self.right_filter.consume(right_input[i]);
-            let out = if self.right_filter.is_empty() != 0 {
-                self.right_filter.median()
+            let (left, right) = (inputs[0][i], inputs[1][i]);
+            if let Some(profile) = self.learner.next(left, right) {
+                self.apply_profile(&profile, &params);
+            }
+            let (left_median, right_median) = if params.mode != Mode::Hpss {
+                self.next_medians(left, right, &params, window_size, offset)
             } else {
-                0.0
+                (0.0, 0.0)
             };
-            right_output[i] = right_input[i] * (1.0 - wet_dry) + out * wet_dry;
+
+            let bypass_left = params.bypass || params.bypass_left;
+            let bypass_right = params.bypass || params.bypass_right;
+            outputs[0][i] = self
+                .left
+                .next(left, left_median, &params, wet_dry, bypass_left);
+            outputs[1][i] = self
+                .right
+                .next(right, right_median, &params, wet_dry, bypass_right);
         }
     }
 
@@ -105,47 +227,701 @@ impl Plugin for MedianFilter {
 }
 
 impl MedianFilter {
-    fn reset_if_changed(&mut self) {
-        let params = Parameters::from(self.params.as_ref());
-        if params.window_size != self.last_window_size {
-            self.left_filter = Filter::new(params.window_size);
-            self.right_filter = Filter::new(params.window_size);
-            self.last_window_size = params.window_size;
+    /// Rebuild the parameters if any have changed since they were last built.
+    /// Returns true if they were rebuilt.
+    fn update_params(&mut self) -> bool {
+        let generation = self.params.generation();
+        if self.generation == Some(generation) {
+            return false;
+        }
+        self.generation = Some(generation);
+        self.parameters = Parameters::from(self.params.as_ref());
+        true
+    }
+
+    /// The current latency of the plugin, in samples.
+    fn latency(&self) -> usize {
+        Parameters::from(self.params.as_ref()).latency()
+    }
+
+    /// Set the de-click threshold and window size from a learned profile, then
+    /// turn Learn back off. This runs on the audio thread, so the host is told
+    /// about the changes later, from its own thread. The profile itself is
+    /// kept with the plugin's saved state.
+    fn apply_profile(&self, profile: &Profile, params: &Parameters) {
+        self.params.learned_threshold.set(profile.threshold_db);
+        self.params
+            .learned_spike_width
+            .set(profile.spike_width as f32);
+
+        let threshold = ParameterType::ClickThreshold
+            .range()
+            .to_normalized(profile.threshold_db);
+        // The threshold can't be exactly zero, since that turns it off.
+        self.params
+            .set_from_audio(threshold.clamp(0.01, 1.0), ParameterType::ClickThreshold);
+
+        // A median removes spikes up to half its window size wide. The window
+        // size is measured in downsampled samples.
+        let width = profile.spike_width.div_ceil(params.downsample);
+        let window_size = (2 * width + 1).min(MAX_WINDOW_SIZE);
+        // Aim for the middle of the step so that rounding lands on the right
+        // window size.
+        self.params.set_from_audio(
+            (window_size as f32 + 0.5) / MAX_WINDOW_SIZE as f32,
+            ParameterType::WindowSize,
+        );
+
+        self.params.set_from_audio(0.0, ParameterType::Learn);
+    }
+
+    /// Feed a sample from each channel into the median path and return the
+    /// current median of each channel. `window_size` and `offset` are in
+    /// downsampled samples.
+    fn next_medians(
+        &mut self,
+        left: f32,
+        right: f32,
+        params: &Parameters,
+        window_size: usize,
+        offset: usize,
+    ) -> (f32, f32) {
+        let left = self.left.antialias(left, params);
+        let right = self.right.antialias(right, params);
+
+        if self.decimation_phase == 0 {
+            let (left, right) = (params.compress(left), params.compress(right));
+            if params.mode == Mode::Slew {
+                let left_slew = self.left.slew.differentiate(left);
+                let right_slew = self.right.slew.differentiate(right);
+                self.median.push(left_slew, right_slew);
+            } else {
+                self.median.push(left, right);
+            }
+
+            if self.hold.is_due() {
+                let joint = params.stereo == Stereo::Joint;
+                let medians = self.median.medians(window_size, offset, joint);
+                // Hold for at least one downsampled sample, which also makes
+                // the linear interpolation glide between downsampled samples.
+                let period = params.update_every.max(params.downsample);
+                self.hold.update(medians, period);
+            }
+        }
+        self.decimation_phase = (self.decimation_phase + 1) % params.downsample;
+
+        self.hold.next(params.update_interpolate)
+    }
+}
+
+/// Holds a pair of values between updates, optionally gliding linearly from
+/// the previous values to the current ones.
+struct Hold {
+    previous: (f32, f32),
+    current: (f32, f32),
+    // The number of samples between updates
+    period: usize,
+    // The number of samples since the last update
+    elapsed: usize,
+}
+
+impl Hold {
+    fn new() -> Hold {
+        Hold {
+            previous: (0.0, 0.0),
+            current: (0.0, 0.0),
+            period: 1,
+            elapsed: 1,
+        }
+    }
+
+    /// Returns true if it is time to call `update`.
+    fn is_due(&self) -> bool {
+        self.elapsed >= self.period
+    }
+
+    /// Set new values to hold for the next `period` samples.
+    fn update(&mut self, values: (f32, f32), period: usize) {
+        self.previous = self.current;
+        self.current = values;
+        self.period = period.max(1);
+        self.elapsed = 0;
+    }
+
+    fn next(&mut self, interpolate: bool) -> (f32, f32) {
+        self.elapsed += 1;
+        if interpolate {
+            let t = self.elapsed as f32 / self.period as f32;
+            (
+                self.previous.0 + (self.current.0 - self.previous.0) * t,
+                self.previous.1 + (self.current.1 - self.previous.1) * t,
+            )
+        } else {
+            self.current
+        }
+    }
+}
+
+/// The fraction of the ceiling below which `soft_limit` leaves the signal alone.
+const LIMIT_KNEE: f32 = 0.8;
+
+/// Softly limit `x` so that its magnitude never exceeds `ceiling`. Samples
+/// below the knee pass through unchanged, and samples above it are smoothly
+/// squashed towards the ceiling.
+fn soft_limit(x: f32, ceiling: f32) -> f32 {
+    let knee = LIMIT_KNEE * ceiling;
+    if x.abs() <= knee {
+        x
+    } else {
+        let range = ceiling - knee;
+        x.signum() * (knee + range * ((x.abs() - knee) / range).tanh())
+    }
+}
+
+/// Converts a signal to its per-sample differences (its slew) and back again.
+/// The integrator is leaky, so that any DC offset introduced while filtering
+/// the differences dies away instead of accumulating.
+struct Slew {
+    last_input: f32,
+    integrator: f32,
+    leak: f32,
+}
+
+impl Slew {
+    fn new() -> Slew {
+        Slew {
+            last_input: 0.0,
+            integrator: 0.0,
+            leak: 0.999,
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        // Leak with a time constant corresponding to about 5 Hz.
+        self.leak = (-2.0 * std::f32::consts::PI * 5.0 / sample_rate).exp();
+    }
+
+    fn differentiate(&mut self, input: f32) -> f32 {
+        let slew = input - self.last_input;
+        self.last_input = input;
+        slew
+    }
+
+    fn integrate(&mut self, slew: f32) -> f32 {
+        self.integrator = flush_denormal(self.integrator * self.leak + slew);
+        self.integrator
+    }
+}
+
+/// The processing state for a single channel.
+struct Channel {
+    // Filters used before and after downsampling
+    antialias_filter: Biquad,
+    reconstruction_filter: Biquad,
+    slew: Slew,
+    hpss: Hpss,
+    delay: Delay,
+    transient: TransientDetector,
+    gate: Gate,
+    // How far the channel is faded over to its dry signal, from 0.0 to 1.0
+    bypass_mix: f32,
+    // How much `bypass_mix` may change per sample
+    bypass_step: f32,
+}
+
+impl Channel {
+    fn new() -> Channel {
+        Channel {
+            antialias_filter: Biquad::default(),
+            reconstruction_filter: Biquad::default(),
+            slew: Slew::new(),
+            hpss: Hpss::new(),
+            delay: Delay::new(MAX_LATENCY),
+            transient: TransientDetector::new(),
+            gate: Gate::new(),
+            bypass_mix: 0.0,
+            bypass_step: 1.0,
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.slew.set_sample_rate(sample_rate);
+        self.gate.set_sample_rate(sample_rate);
+        self.bypass_step = 1.0 / (BYPASS_FADE_MS / 1000.0 * sample_rate);
+    }
+
+    /// Update any state which depends on the parameters. This should be called
+    /// once per block.
+    fn set_params(&mut self, params: &Parameters) {
+        self.transient
+            .set_params(params.sensitivity, params.recovery, params.sample_rate);
+
+        // Cut off a little below the downsampled Nyquist frequency.
+        let cutoff = 0.45 * params.sample_rate / params.downsample as f32;
+        self.antialias_filter
+            .set_lowpass(cutoff, FRAC_1_SQRT_2, params.sample_rate);
+        self.reconstruction_filter
+            .set_lowpass(cutoff, FRAC_1_SQRT_2, params.sample_rate);
+    }
+
+    /// Filter a sample before it is downsampled. Without filtering, the
+    /// downsampling aliases, which can be desirable for a dirtier sound.
+    fn antialias(&mut self, input: f32, params: &Parameters) -> f32 {
+        if params.filter_downsample() {
+            self.antialias_filter.next(input)
+        } else {
+            input
+        }
+    }
+
+    /// Process a single sample. `median` is the output of the median filter,
+    /// which is unused in `Mode::Hpss`. If `bypass` is true, the output fades
+    /// to the input, delayed only to stay in line with the other channel.
+    fn next(
+        &mut self,
+        input: f32,
+        median: f32,
+        params: &Parameters,
+        wet_dry: f32,
+        bypass: bool,
+    ) -> f32 {
+        let out = match params.mode {
+            Mode::Median => params.expand(median),
+            // Each downsampled difference spans several samples, so spread it
+            // out over them.
+            Mode::Slew => {
+                let slope = median / params.downsample as f32;
+                params.expand(self.slew.integrate(slope))
+            }
+            Mode::Hpss => self.hpss.next(input, params.balance),
+        };
+        // Smooth out the steps left by downsampling.
+        let out = if params.mode != Mode::Hpss && params.filter_downsample() {
+            self.reconstruction_filter.next(out)
+        } else {
+            out
+        };
+        // Delay the dry signal to line up with the wet signal
+        let dry = self.delay.next(input, params.latency());
+
+        // Only replace the samples which stray far enough from the median to
+        // be clicks, leaving everything else untouched.
+        let out = match params.click_threshold {
+            Some(threshold)
+                if params.mode != Mode::Hpss && (out - dry).abs() < db_to_gain(threshold) =>
+            {
+                dry
+            }
+            _ => out,
+        };
+
+        // On transients, fall back to the dry signal, which is the same as
+        // collapsing the window down to a single sample. The detector listens
+        // to the delayed dry signal, so that it lines up with the wet signal.
+        let transient = self.transient.next(dry);
+        let out = out + (dry - out) * transient;
+
+        // Like the transient detector, the gate follows the delayed signal.
+        let gate = match params.gate_mode {
+            GateMode::Off => 1.0,
+            GateMode::Below => self.gate.next(dry, params.gate_threshold, true),
+            GateMode::Above => self.gate.next(dry, params.gate_threshold, false),
+        };
+        let out = dry + (out - dry) * gate;
+
+        let out = dry * (1.0 - wet_dry) + out * wet_dry;
+        // The residual is whatever the filter removed from the signal.
+        let out = if params.listen_residual {
+            dry - out
+        } else {
+            out
+        };
+
+        let out = match params.ceiling {
+            Some(ceiling) => soft_limit(out, db_to_gain(ceiling)),
+            None => out,
+        };
+
+        let target = if bypass { 1.0 } else { 0.0 };
+        self.bypass_mix = if self.bypass_mix < target {
+            (self.bypass_mix + self.bypass_step).min(target)
+        } else {
+            (self.bypass_mix - self.bypass_step).max(target)
+        };
+        out + (dry - out) * self.bypass_mix
+    }
+}
+
+/// The processing done on the wet signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// A running median over the last `window_size` samples.
+    Median,
+    /// A running median over the differences between samples, which are then
+    /// integrated back into a signal. This removes clicks while keeping more
+    /// of the low end than `Median`.
+    Slew,
+    /// Harmonic/percussive separation, using medians across the spectrogram.
+    Hpss,
+}
+
+impl From<f32> for Mode {
+    fn from(x: f32) -> Self {
+        match (x * 3.0) as usize {
+            0 => Mode::Median,
+            1 => Mode::Slew,
+            _ => Mode::Hpss,
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Median => write!(f, "Median"),
+            Mode::Slew => write!(f, "Slew"),
+            Mode::Hpss => write!(f, "HPSS"),
+        }
+    }
+}
+
+/// Where the median window sits relative to the output sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Alignment {
+    /// The window covers only past samples. This adds no latency.
+    Causal,
+    /// The window is centered on the output sample. This adds half of the
+    /// largest window of latency, but doesn't smear transients forwards in
+    /// time.
+    Centered,
+}
+
+impl From<f32> for Alignment {
+    fn from(x: f32) -> Self {
+        if x < 0.5 {
+            Alignment::Causal
+        } else {
+            Alignment::Centered
+        }
+    }
+}
+
+impl std::fmt::Display for Alignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Alignment::Causal => write!(f, "Causal"),
+            Alignment::Centered => write!(f, "Centered"),
+        }
+    }
+}
+
+/// When the level gate lets the median path engage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GateMode {
+    /// The median path is always engaged.
+    Off,
+    /// Engage when the input is quieter than the threshold.
+    Below,
+    /// Engage when the input is louder than the threshold.
+    Above,
+}
+
+impl From<f32> for GateMode {
+    fn from(x: f32) -> Self {
+        match (x * 3.0) as usize {
+            0 => GateMode::Off,
+            1 => GateMode::Below,
+            _ => GateMode::Above,
+        }
+    }
+}
+
+impl std::fmt::Display for GateMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GateMode::Off => write!(f, "Off"),
+            GateMode::Below => write!(f, "Below"),
+            GateMode::Above => write!(f, "Above"),
+        }
+    }
+}
+
+/// How the two channels are filtered in `Mode::Median` and `Mode::Slew`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stereo {
+    /// Each channel is filtered on its own.
+    Independent,
+    /// Each channel's window also includes the other channel's samples.
+    Joint,
+}
+
+impl From<f32> for Stereo {
+    fn from(x: f32) -> Self {
+        if x < 0.5 {
+            Stereo::Independent
+        } else {
+            Stereo::Joint
+        }
+    }
+}
+
+impl std::fmt::Display for Stereo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stereo::Independent => write!(f, "Independent"),
+            Stereo::Joint => write!(f, "Joint"),
         }
     }
 }
 
+#[derive(Clone, Copy)]
 struct Parameters {
     window_size: usize,
-    wet_dry: f32,
+    mode: Mode,
+    balance: f32,
+    alignment: Alignment,
+    sensitivity: f32,
+    /// The transient recovery time, in milliseconds
+    recovery: f32,
+    gate_mode: GateMode,
+    /// The gate threshold, in decibels
+    gate_threshold: f32,
+    listen_residual: bool,
+    /// How much to randomly perturb the window size, from 0.0 to 1.0
+    jitter: f32,
+    /// The number of samples between each new random window size
+    jitter_rate: usize,
+    jitter_seed: u32,
+    stereo: Stereo,
+    /// The number of samples between recomputing the median
+    update_every: usize,
+    /// If true, glide between medians instead of holding them
+    update_interpolate: bool,
+    bypass_left: bool,
+    bypass_right: bool,
+    /// If true, the median path works on mu-law compressed samples
+    compand: bool,
+    /// The output ceiling in decibels, or None if the limiter is off
+    ceiling: Option<f32>,
+    bypass: bool,
+    /// The factor to downsample by before the median path
+    downsample: usize,
+    /// If true, skip the anti-aliasing and reconstruction filters
+    downsample_dirty: bool,
+    /// The de-click threshold in decibels, or None if every sample is replaced
+    click_threshold: Option<f32>,
+    /// If true, a Learn pass should be running
+    learn: bool,
+    sample_rate: f32,
+}
+
+impl Parameters {
+    /// Returns true if the median path is downsampled with filtering.
+    fn filter_downsample(&self) -> bool {
+        self.downsample > 1 && !self.downsample_dirty
+    }
+
+    /// The delay, in samples, between the input and the wet signal.
+    fn latency(&self) -> usize {
+        match self.mode {
+            Mode::Median | Mode::Slew => match self.alignment {
+                Alignment::Causal => 0,
+                Alignment::Centered => CENTERED_LATENCY * self.downsample,
+            },
+            Mode::Hpss => spectral::FRAME_SIZE,
+        }
+    }
+
+    /// Encode a sample going into the median path. Since mu-law is monotonic,
+    /// this doesn't change which sample a plain median picks, but it does
+    /// change anything which combines samples, such as the slew integrator and
+    /// the linear update interpolation, so that large spikes count for less.
+    fn compress(&self, x: f32) -> f32 {
+        if self.compand {
+            x.signum() * (1.0 + MU * x.abs()).ln() / (1.0 + MU).ln()
+        } else {
+            x
+        }
+    }
+
+    /// Decode a sample coming out of the median path. This is the inverse of
+    /// `compress`.
+    fn expand(&self, y: f32) -> f32 {
+        if self.compand {
+            y.signum() * ((1.0 + MU).powf(y.abs()) - 1.0) / MU
+        } else {
+            y
+        }
+    }
 }
 
 impl From<&RawParameters> for Parameters {
     fn from(params: &RawParameters) -> Self {
         Parameters {
-            window_size: ((params.window_size.get() * 100.0) as usize).max(1),
-            wet_dry: params.wet_dry.get(),
+            window_size: (params.real(ParameterType::WindowSize) as usize).max(1),
+            mode: Mode::from(params.mode.get()),
+            balance: params.balance.get(),
+            alignment: Alignment::from(params.alignment.get()),
+            sensitivity: params.sensitivity.get(),
+            recovery: params.real(ParameterType::Recovery).max(1.0),
+            gate_mode: GateMode::from(params.gate_mode.get()),
+            gate_threshold: params.real(ParameterType::GateThreshold),
+            listen_residual: params.listen_residual.get() > 0.5,
+            jitter: params.jitter.get(),
+            jitter_rate: params.real(ParameterType::JitterRate) as usize,
+            jitter_seed: params.real(ParameterType::JitterSeed) as u32,
+            stereo: Stereo::from(params.stereo.get()),
+            update_every: params.real(ParameterType::UpdateEvery).round() as usize,
+            update_interpolate: params.update_interpolate.get() > 0.5,
+            bypass_left: params.bypass_left.get() > 0.5,
+            bypass_right: params.bypass_right.get() > 0.5,
+            compand: params.compand.get() > 0.5,
+            ceiling: if params.ceiling.get() < 1.0 {
+                Some(params.real(ParameterType::Ceiling))
+            } else {
+                None
+            },
+            bypass: params.bypass.get() > 0.5,
+            downsample: 1 << params.real(ParameterType::Downsample) as usize,
+            downsample_dirty: params.downsample_dirty.get() > 0.5,
+            click_threshold: if params.click_threshold.get() > 0.0 {
+                Some(params.real(ParameterType::ClickThreshold))
+            } else {
+                None
+            },
+            learn: params.learn.get() > 0.5,
+            sample_rate: params.sample_rate.get(),
         }
     }
 }
 
-macro_rules! table {
-    ($macro:ident) => {
-        $macro! {
-        //  RawParameter identifier, ParameterType identifier
-            RawParameters,           ParameterType;
-        //  variant      field_name    name            idx  default  strings
-            WetDry,      wet_dry,      "Wet/Dry",      0,   0.5,     |x: f32| make_strings(x * 100.0, "% Wet");
-            WindowSize,  window_size,  "Window Size",  1,   0.5,     |x: usize| (format!("{}", x), " Samples".to_string());
-        }
-    };
+/// Label option `index` of the Downsample parameter with its factor.
+fn downsample_label(index: usize, _count: usize) -> String {
+    format!("{}x", 1 << index)
+}
+
+/// The raw parameter values that a host DAW will set and modify.
+/// These are unscaled and are always in the [0.0, 1.0] range
+#[derive(VstParameters)]
+#[parameters(parameter_type = ParameterType, presets = PRESETS)]
+pub struct RawParameters {
+    #[parameter(name = "Wet/Dry", default = 0.5, range = Range::linear(0.0, 100.0, "% Wet"))]
+    #[smoothed]
+    wet_dry: AtomicFloat,
+    #[parameter(name = "Window Size", default = 0.5, range = Range::linear(0.0, MAX_WINDOW_SIZE as f32, "smp"), strings = |x: usize, params: &Parameters| (format!("{} smp / {:.2}", x, x as f32 / params.sample_rate * 1000.0), "ms".to_string()))]
+    #[smoothed]
+    window_size: AtomicFloat,
+    #[parameter(name = "Mode", default = 0.0, range = Range::choice(3, choice_label::<Mode>))]
+    mode: AtomicFloat,
+    #[parameter(name = "H/P Balance", default = 0.5, range = Range::linear(0.0, 100.0, "% Percussive"))]
+    balance: AtomicFloat,
+    #[parameter(name = "Alignment", default = 0.0, range = Range::choice(2, choice_label::<Alignment>))]
+    alignment: AtomicFloat,
+    #[parameter(name = "Transient Sensitivity", default = 0.0, range = Range::linear(0.0, 100.0, "%"))]
+    sensitivity: AtomicFloat,
+    #[parameter(name = "Transient Recovery", default = 0.2, range = Range::linear(0.0, 500.0, "ms"), strings = |x: f32, _| make_strings(x, "ms"))]
+    recovery: AtomicFloat,
+    #[parameter(name = "Gate", default = 0.0, range = Range::choice(3, choice_label::<GateMode>))]
+    gate_mode: AtomicFloat,
+    #[parameter(name = "Gate Threshold", default = 0.5, range = Range::linear(-80.0, 0.0, "dB"))]
+    gate_threshold: AtomicFloat,
+    #[parameter(variant = Listen, name = "Listen", default = 0.0, range = Range::toggle("Output", "Residual"))]
+    listen_residual: AtomicFloat,
+    #[parameter(name = "Jitter", default = 0.0, range = Range::linear(0.0, 100.0, "%"))]
+    jitter: AtomicFloat,
+    #[parameter(name = "Jitter Rate", default = 0.5, range = Range::log(16.0, 8192.0, "smp"), strings = |x: usize, _| (format!("{}", x), "smp".to_string()))]
+    jitter_rate: AtomicFloat,
+    #[parameter(name = "Jitter Seed", default = 0.0, range = Range::linear(0.0, 999.0, ""), strings = |x: u32, _| (format!("{}", x), "".to_string()))]
+    jitter_seed: AtomicFloat,
+    #[parameter(name = "Stereo", default = 0.0, range = Range::choice(2, choice_label::<Stereo>))]
+    stereo: AtomicFloat,
+    #[parameter(name = "Update Every", default = 0.0, range = Range::log(1.0, 1024.0, "smp"), strings = |x: usize, _| (format!("{}", x), "smp".to_string()))]
+    update_every: AtomicFloat,
+    #[parameter(name = "Update Interpolation", default = 0.0, range = Range::toggle("Hold", "Linear"))]
+    update_interpolate: AtomicFloat,
+    #[parameter(variant = BypassL, name = "Bypass L", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    bypass_left: AtomicFloat,
+    #[parameter(variant = BypassR, name = "Bypass R", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    bypass_right: AtomicFloat,
+    #[parameter(name = "Companding", default = 0.0, range = Range::toggle("OFF", "Mu-law"))]
+    compand: AtomicFloat,
+    #[parameter(name = "Ceiling", default = 1.0, range = Range::linear(-24.0, 0.0, "dB").with_off(1.0))]
+    ceiling: AtomicFloat,
+    #[parameter(name = "Bypass", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    bypass: AtomicFloat,
+    #[parameter(name = "Downsample", default = 0.0, range = Range::choice(DOWNSAMPLE_FACTORS, downsample_label))]
+    downsample: AtomicFloat,
+    #[parameter(name = "Downsample Filter", default = 0.0, range = Range::toggle("Clean", "Dirty"))]
+    downsample_dirty: AtomicFloat,
+    #[parameter(name = "De-click Threshold", default = 0.0, range = Range::linear(MIN_THRESHOLD_DB, 0.0, "dB").with_off(0.0))]
+    click_threshold: AtomicFloat,
+    #[parameter(name = "Learn", default = 0.0, range = Range::toggle("OFF", "Learning"))]
+    learn: AtomicFloat,
+    /// The de-click threshold found by the last Learn pass, in decibels
+    #[state(default = 0.0)]
+    learned_threshold: AtomicFloat,
+    /// The click width found by the last Learn pass, in samples, or 0.0 if
+    /// Learn hasn't been run
+    #[state(default = 0.0)]
+    learned_spike_width: AtomicFloat,
+    /// The index of the most recently selected built-in program
+    current_preset: AtomicI32,
+    /// Bumped whenever a parameter changes. See `generation`.
+    generation: AtomicUsize,
+    /// Changes made on the audio thread which the host hasn't been told about
+    pending: PendingEdits,
+    /// The sample rate most recently given by the host
+    pub sample_rate: AtomicFloat,
+    /// The host callback, used for communicating with the VST host
+    pub host: HostCallback,
 }
 
-impl ParameterType {
-    pub const COUNT: usize = 2;
+impl Automatable for ParameterType {
+    fn can_be_automated(&self) -> bool {
+        *self != ParameterType::Learn
+    }
 }
 
-impl_all! {RawParameters, ParameterType, table}
+/// The built-in programs. The values are normalized, so, for example, a
+/// WindowSize of 0.05 is a 5 sample window.
+const PRESETS: &[Preset<ParameterType>] = &[
+    Preset {
+        name: "Default",
+        values: &[],
+    },
+    Preset {
+        name: "De-crackle",
+        values: &[
+            (ParameterType::WetDry, 1.0),
+            (ParameterType::WindowSize, 0.05),
+            (ParameterType::Alignment, 1.0),
+        ],
+    },
+    Preset {
+        name: "Smoother",
+        values: &[
+            (ParameterType::WetDry, 0.8),
+            (ParameterType::WindowSize, 0.3),
+            (ParameterType::Alignment, 1.0),
+        ],
+    },
+    Preset {
+        name: "Lo-fi crush",
+        values: &[
+            (ParameterType::WetDry, 1.0),
+            (ParameterType::WindowSize, 1.0),
+        ],
+    },
+    Preset {
+        name: "Envelope tamer",
+        values: &[
+            (ParameterType::WetDry, 1.0),
+            (ParameterType::WindowSize, 0.6),
+            (ParameterType::Sensitivity, 0.7),
+            (ParameterType::Recovery, 0.2),
+        ],
+    },
+];
 
 // Export symbols for main
 vst::plugin_main!(MedianFilter);
@@ -0,0 +1,197 @@
+use std::cmp::Ordering;
+
+/// Returns the median of `values`, reordering them in the process. For an even
+/// number of values, this is the upper of the two middle values.
+pub fn median(values: &mut [f32]) -> f32 {
+    let mid = values.len() / 2;
+    let (_, median, _) =
+        values.select_nth_unstable_by(mid, |a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    *median
+}
+
+/// A running median over the recent history of a signal. Unlike a filter with
+/// a fixed window, the window size and position may change on every sample
+/// without losing any history, which allows for smooth window sweeps.
+pub struct RunningMedian {
+    history: Vec<f32>,
+    // The index the next sample will be written to.
+    pos: usize,
+    scratch: Vec<f32>,
+}
+
+impl RunningMedian {
+    /// Create a running median able to look `capacity` samples into the past.
+    pub fn new(capacity: usize) -> RunningMedian {
+        RunningMedian {
+            history: vec![0.0; capacity],
+            pos: 0,
+            scratch: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, input: f32) {
+        self.history[self.pos] = input;
+        self.pos = (self.pos + 1) % self.history.len();
+    }
+
+    /// Returns the sample `offset` samples before the most recently pushed
+    /// sample.
+    pub fn get(&self, offset: usize) -> f32 {
+        let len = self.history.len();
+        self.history[(self.pos + len - 1 - offset.min(len - 1)) % len]
+    }
+
+    /// Append the `size` samples ending `offset` samples before the most
+    /// recently pushed sample to `values`.
+    pub fn extend_window(&self, values: &mut Vec<f32>, size: usize, offset: usize) {
+        let len = self.history.len();
+        let end = offset.min(len - 1);
+        let size = size.clamp(1, len - end);
+        let newest = self.pos + len - 1;
+        values.extend((end..end + size).map(|k| self.history[(newest - k) % len]));
+    }
+
+    /// Returns the median of the `size` samples ending `offset` samples before
+    /// the most recently pushed sample.
+    pub fn median(&mut self, size: usize, offset: usize) -> f32 {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        self.extend_window(&mut scratch, size, offset);
+        let median = median(&mut scratch);
+        self.scratch = scratch;
+        median
+    }
+}
+
+/// Running medians for a pair of channels, which may optionally be pooled
+/// together.
+pub struct StereoMedian {
+    left: RunningMedian,
+    right: RunningMedian,
+    scratch: Vec<f32>,
+}
+
+impl StereoMedian {
+    pub fn new(capacity: usize) -> StereoMedian {
+        StereoMedian {
+            left: RunningMedian::new(capacity),
+            right: RunningMedian::new(capacity),
+            scratch: Vec::with_capacity(capacity * 3),
+        }
+    }
+
+    /// Push a sample to each channel.
+    pub fn push(&mut self, left: f32, right: f32) {
+        self.left.push(left);
+        self.right.push(right);
+    }
+
+    /// Return the median of each channel's window. If `joint` is true, each
+    /// median is instead taken over the windows of both channels (a 2D median
+    /// over time and channel). The channel's own window is counted twice, so
+    /// that uncorrelated noise is rejected without collapsing the output to
+    /// mono.
+    pub fn medians(&mut self, size: usize, offset: usize, joint: bool) -> (f32, f32) {
+        if joint {
+            (
+                Self::joint_median(&mut self.scratch, &self.left, &self.right, size, offset),
+                Self::joint_median(&mut self.scratch, &self.right, &self.left, size, offset),
+            )
+        } else {
+            (
+                self.left.median(size, offset),
+                self.right.median(size, offset),
+            )
+        }
+    }
+
+    fn joint_median(
+        scratch: &mut Vec<f32>,
+        own: &RunningMedian,
+        other: &RunningMedian,
+        size: usize,
+        offset: usize,
+    ) -> f32 {
+        scratch.clear();
+        own.extend_window(scratch, size, offset);
+        own.extend_window(scratch, size, offset);
+        other.extend_window(scratch, size, offset);
+        median(scratch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::rng::Rng;
+
+    const CAPACITY: usize = 64;
+
+    // The median of the `size` samples ending `offset` samples before the
+    // end of `history`, by sorting them.
+    fn brute_force(history: &[f32], size: usize, offset: usize) -> f32 {
+        let end = history.len() - offset;
+        let mut window = history[end - size..end].to_vec();
+        window.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        window[window.len() / 2]
+    }
+
+    #[test]
+    fn matches_brute_force_while_resizing() {
+        let mut rng = Rng::new(0);
+        let mut running = RunningMedian::new(CAPACITY);
+        // The running median starts out full of silence.
+        let mut history = vec![0.0; CAPACITY];
+        for _ in 0..2000 {
+            let input = rng.next_bipolar();
+            running.push(input);
+            history.push(input);
+
+            // A new window size and position every sample
+            let offset = rng.next_u32() as usize % (CAPACITY / 2);
+            let size = 1 + rng.next_u32() as usize % (CAPACITY - offset);
+            assert_eq!(
+                running.median(size, offset),
+                brute_force(&history, size, offset)
+            );
+            assert_eq!(running.get(offset), history[history.len() - 1 - offset]);
+        }
+    }
+
+    #[test]
+    fn stereo_matches_brute_force() {
+        let mut rng = Rng::new(1);
+        let mut stereo = StereoMedian::new(CAPACITY);
+        let mut left = vec![0.0; CAPACITY];
+        let mut right = vec![0.0; CAPACITY];
+        for _ in 0..2000 {
+            let (l, r) = (rng.next_bipolar(), rng.next_bipolar());
+            stereo.push(l, r);
+            left.push(l);
+            right.push(r);
+
+            let offset = rng.next_u32() as usize % (CAPACITY / 2);
+            let size = 1 + rng.next_u32() as usize % (CAPACITY - offset);
+            assert_eq!(
+                stereo.medians(size, offset, false),
+                (
+                    brute_force(&left, size, offset),
+                    brute_force(&right, size, offset)
+                )
+            );
+
+            // A joint median counts the channel's own window twice.
+            let end = left.len() - offset;
+            let joint = |own: &[f32], other: &[f32]| {
+                let mut pooled = own[end - size..end].to_vec();
+                pooled.extend_from_slice(&own[end - size..end]);
+                pooled.extend_from_slice(&other[end - size..end]);
+                brute_force(&pooled, pooled.len(), 0)
+            };
+            assert_eq!(
+                stereo.medians(size, offset, true),
+                (joint(&left, &right), joint(&right, &left))
+            );
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+/// The number of samples in each STFT frame.
+pub const FRAME_SIZE: usize = 1024;
+/// The number of samples between the start of consecutive frames (75% overlap)
+pub const HOP_SIZE: usize = FRAME_SIZE / 4;
+/// The number of unique frequency bins in a frame of a real signal.
+pub const NUM_BINS: usize = FRAME_SIZE / 2 + 1;
+
+/// A streaming short-time Fourier transform. Samples are fed in one at a time
+/// and, once every `HOP_SIZE` samples, a frame of spectrum is handed to a
+/// callback for modification before being resynthesized with overlap-add.
+/// This introduces `FRAME_SIZE` samples of latency.
+pub struct Stft {
+    forward: Arc<dyn Fft<f32>>,
+    inverse: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    // The last FRAME_SIZE input samples, oldest first.
+    input: Vec<f32>,
+    // The overlap-add accumulator. The first HOP_SIZE samples are the ones
+    // which have received all of their contributions.
+    output: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    // The position within the current hop.
+    pos: usize,
+}
+
+impl Stft {
+    pub fn new() -> Stft {
+        let mut planner = FftPlanner::new();
+        // Periodic Hann window, used for both analysis and synthesis
+        let window = (0..FRAME_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME_SIZE as f32).cos())
+            .collect();
+        Stft {
+            forward: planner.plan_fft_forward(FRAME_SIZE),
+            inverse: planner.plan_fft_inverse(FRAME_SIZE),
+            window,
+            input: vec![0.0; FRAME_SIZE],
+            output: vec![0.0; FRAME_SIZE],
+            spectrum: vec![Complex::new(0.0, 0.0); FRAME_SIZE],
+            pos: 0,
+        }
+    }
+
+    /// Consume one input sample and return one output sample. `process_frame`
+    /// is called with the full (not just the unique bins) spectrum of a frame
+    /// whenever a new frame is ready.
+    pub fn process(
+        &mut self,
+        input: f32,
+        mut process_frame: impl FnMut(&mut [Complex<f32>]),
+    ) -> f32 {
+        self.input[FRAME_SIZE - HOP_SIZE + self.pos] = input;
+        let out = self.output[self.pos];
+        self.pos += 1;
+
+        if self.pos == HOP_SIZE {
+            self.pos = 0;
+
+            for i in 0..FRAME_SIZE {
+                self.spectrum[i] = Complex::new(self.input[i] * self.window[i], 0.0);
+            }
+            self.forward.process(&mut self.spectrum);
+            process_frame(&mut self.spectrum);
+            self.inverse.process(&mut self.spectrum);
+
+            // A squared Hann window at 75% overlap sums to 1.5, and the
+            // inverse FFT is unnormalized.
+            let scale = 1.0 / (FRAME_SIZE as f32 * 1.5);
+            self.output.copy_within(HOP_SIZE.., 0);
+            for x in &mut self.output[FRAME_SIZE - HOP_SIZE..] {
+                *x = 0.0;
+            }
+            for i in 0..FRAME_SIZE {
+                self.output[i] += self.spectrum[i].re * self.window[i] * scale;
+            }
+
+            self.input.copy_within(HOP_SIZE.., 0);
+        }
+        out
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A stereo loop waiting to be written to disk.
+struct Capture {
+    left: Vec<f32>,
+    right: Vec<f32>,
+    sample_rate: f32,
+}
+
+/// Writes captured loops to WAV files on a background thread, so that the
+/// audio thread never touches the disk. The thread exits once the exporter
+/// is dropped.
+pub struct Exporter {
+    capture: Arc<Mutex<Capture>>,
+    sender: SyncSender<()>,
+}
+
+impl Exporter {
+    /// Create an exporter able to hold loops up to `capacity` samples long.
+    pub fn new(capacity: usize) -> Exporter {
+        let capture = Arc::new(Mutex::new(Capture {
+            left: Vec::with_capacity(capacity),
+            right: Vec::with_capacity(capacity),
+            sample_rate: 44100.0,
+        }));
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let shared = Arc::clone(&capture);
+        thread::spawn(move || {
+            for () in receiver {
+                let capture = match shared.lock() {
+                    Ok(capture) => capture,
+                    Err(_) => return,
+                };
+                if capture.left.is_empty() {
+                    continue;
+                }
+                // There's nowhere to report a failed write to from here, so
+                // the loop is dropped and the next save tries again.
+                let _ = write_wav(&export_path(), &capture);
+            }
+        });
+        Exporter { capture, sender }
+    }
+
+    /// Make room for loops up to `capacity` samples long. This allocates, so
+    /// it shouldn't be called while processing.
+    pub fn set_capacity(&self, capacity: usize) {
+        if let Ok(mut capture) = self.capture.lock() {
+            capture.left.reserve(capacity);
+            capture.right.reserve(capacity);
+        }
+    }
+
+    /// Copy a loop using `copy` and queue it to be written. `copy` is given
+    /// the left and right channels to fill. Returns false without calling
+    /// `copy` if the previous loop is still being written.
+    pub fn export(
+        &self,
+        sample_rate: f32,
+        copy: impl FnOnce(&mut Vec<f32>, &mut Vec<f32>),
+    ) -> bool {
+        {
+            let mut capture = match self.capture.try_lock() {
+                Ok(capture) => capture,
+                Err(_) => return false,
+            };
+            let capture = &mut *capture;
+            copy(&mut capture.left, &mut capture.right);
+            capture.sample_rate = sample_rate;
+        }
+        self.sender.try_send(()).is_ok()
+    }
+}
+
+/// Returns a new file name in the user's home directory, falling back to the
+/// temporary directory.
+fn export_path() -> PathBuf {
+    let dir = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0);
+    dir.join(format!("stutter-{}.wav", time))
+}
+
+/// Write `capture` as a 32-bit float stereo WAV file.
+fn write_wav(path: &Path, capture: &Capture) -> io::Result<()> {
+    const CHANNELS: u16 = 2;
+    const BYTES_PER_SAMPLE: u16 = 4;
+    let frames = capture.left.len().min(capture.right.len());
+    let data_size = (frames * (CHANNELS * BYTES_PER_SAMPLE) as usize) as u32;
+    let sample_rate = capture.sample_rate.round() as u32;
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_size).to_le_bytes())?;
+    file.write_all(b"WAVE")?;
+    file.write_all(b"fmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    // Format 3 is IEEE float
+    file.write_all(&3u16.to_le_bytes())?;
+    file.write_all(&CHANNELS.to_le_bytes())?;
+    file.write_all(&sample_rate.to_le_bytes())?;
+    file.write_all(&(sample_rate * (CHANNELS * BYTES_PER_SAMPLE) as u32).to_le_bytes())?;
+    file.write_all(&(CHANNELS * BYTES_PER_SAMPLE).to_le_bytes())?;
+    file.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+    file.write_all(b"data")?;
+    file.write_all(&data_size.to_le_bytes())?;
+    for (left, right) in capture.left.iter().zip(&capture.right) {
+        file.write_all(&left.to_le_bytes())?;
+        file.write_all(&right.to_le_bytes())?;
+    }
+    file.flush()
+}
@@ -1,38 +1,173 @@
-#[macro_use]
-extern crate common;
+mod export;
 
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicI32, AtomicUsize},
+    Arc,
+};
 
 use vst::{
-    api::Supported,
-    buffer::AudioBuffer,
+    api::{Events, Supported},
+    buffer::{AudioBuffer, SendEventBuffer},
+    event::MidiEvent,
     host::Host,
     plugin::{CanDo, Category, HostCallback, Info, Plugin, PluginParameters},
     util::AtomicFloat,
 };
 
-use common::{ease_in_expo, make_strings};
+use common::{
+    db_to_gain,
+    delay::Delay,
+    gesture::PendingEdits,
+    initial_delay,
+    lfo::{Lfo, LfoRate, LfoShape},
+    midi::{MidiMessage, MidiParser, TimedMessage},
+    range::{choice_label, Range},
+    ring_buffer::{RingBuffer, MAX_VOICES},
+    rng::{instance_seed, Rng},
+    smooth::Smoothing,
+    transport::Transport,
+    Automatable, VstParameters,
+};
+
+use export::Exporter;
 
-const MAX_BUFFER_SIZE: usize = 32768; // 2^16
+/// The longest loop that can be captured, in seconds. The buffers are sized
+/// for this at the host's sample rate.
+const MAX_BUFFER_SECONDS: f32 = 8.0;
+/// The number of steps in the step pattern.
+const PATTERN_STEPS: usize = 16;
+/// The most repeats the Repeats parameter can select before it becomes
+/// infinite.
+const MAX_REPEATS: usize = 64;
+/// How far ahead Lookahead lets quantized triggers see, in milliseconds.
+const LOOKAHEAD_MS: f32 = 50.0;
+/// The most MIDI events sent to the host per block. Any more are dropped.
+const MAX_MIDI_OUT_EVENTS: usize = 256;
+/// The most MIDI messages which can be received in a single block. Any more
+/// are dropped.
+const MAX_MIDI_IN_EVENTS: usize = 256;
+/// The most slices the loop can be divided into.
+const MAX_SLICES: usize = 16;
+/// The number of options a CC parameter has: off, and then each MIDI CC.
+const CC_OPTIONS: usize = 129;
+/// How long the wet/dry takes to follow automation, in milliseconds.
+const SMOOTHING_MS: f32 = 20.0;
 
 struct Stutter {
     params: Arc<RawParameters>,
     ringbuf_left: RingBuffer,
     ringbuf_right: RingBuffer,
     last_trigger_state: bool,
+    // True while the trigger note is held down.
+    midi_trigger: bool,
+    // The most recent note played while Key Track is on, which sets the loop
+    // length.
+    key_note: Option<u8>,
+    // The notes held down while Note Divisions is on, in the order they were
+    // played. The last note sets the loop length.
+    held_notes: Vec<u8>,
+    // True if a new note took over while Key Track or Note Divisions is on,
+    // which restarts the loop.
+    note_retrigger: bool,
+    // True while the trigger CC is at or above its halfway point.
+    cc_trigger: bool,
+    // The Trigger parameter and trigger note, as of the last grid line.
+    manual_trigger: bool,
+    // True while an automatic trigger slot is playing.
+    auto_trigger: bool,
+    // The automatic trigger slot the position is in, counting from the start
+    // of the pattern's position, or None if automatic triggering is off.
+    auto_slot: Option<i64>,
+    threshold_trigger: ThresholdTrigger,
+    // The position of the step pattern, automatic trigger slots and retrigger
+    // LFO, in quarter notes.
+    pattern_position: f64,
+    // The step the pattern is on, counting from the start of the pattern's
+    // position, or None if the pattern is off.
+    pattern_step: Option<i64>,
+    // True while the pattern is on a step which is on.
+    pattern_trigger: bool,
+    // True while the retrigger LFO is above its threshold.
+    lfo_trigger: bool,
+    // The retrigger LFO, which follows the pattern's position.
+    lfo: Lfo,
+    rng: Rng,
+    // The number of samples since the stutter was last triggered.
+    ramp_elapsed: usize,
+    // The loop length, before ramping, as of the last block.
+    last_buffer_size: usize,
+    // The wet/dry follows this, so that it ramps to new values instead of
+    // jumping.
+    smoothed: SmoothedParameters,
+    exporter: Exporter,
+    // The MIDI messages received for the next block, which are applied at
+    // their own samples as it is processed.
+    midi_in: Vec<TimedMessage>,
+    midi_parser: MidiParser,
+    note_out: NoteOut,
+    // The input, delayed by the lookahead.
+    delay_left: Delay,
+    delay_right: Delay,
+    // The parameters as of `generation`, rebuilt only when it moves.
+    parameters: Parameters,
+    generation: usize,
 }
 
 impl Plugin for Stutter {
     fn new(host: HostCallback) -> Self {
+        let params = RawParameters::default(host);
+        let generation = params.generation();
+        let parameters = Parameters::from(&params);
+        let smoothed = SmoothedParameters::new(&params, Smoothing::Linear, SMOOTHING_MS);
+        // Both channels share a seed so that they shuffle the same slices.
+        let seed = instance_seed();
         Stutter {
-            params: Arc::new(RawParameters::default(host)),
-            ringbuf_left: RingBuffer::new(MAX_BUFFER_SIZE / 2),
-            ringbuf_right: RingBuffer::new(MAX_BUFFER_SIZE / 2),
+            params: Arc::new(params),
+            ringbuf_left: RingBuffer::new(max_buffer_size(44100.0), seed),
+            ringbuf_right: RingBuffer::new(max_buffer_size(44100.0), seed),
             last_trigger_state: false,
+            midi_trigger: false,
+            key_note: None,
+            // Each note can only be held once, so this never grows.
+            held_notes: Vec::with_capacity(128),
+            note_retrigger: false,
+            cc_trigger: false,
+            manual_trigger: false,
+            auto_trigger: false,
+            auto_slot: None,
+            threshold_trigger: ThresholdTrigger::new(),
+            pattern_position: 0.0,
+            pattern_step: None,
+            pattern_trigger: false,
+            lfo_trigger: false,
+            lfo: Lfo::new(1),
+            rng: Rng::new(0),
+            ramp_elapsed: 0,
+            last_buffer_size: 0,
+            smoothed,
+            exporter: Exporter::new(max_buffer_size(44100.0)),
+            midi_in: Vec::with_capacity(MAX_MIDI_IN_EVENTS),
+            midi_parser: MidiParser::default(),
+            note_out: NoteOut::new(),
+            delay_left: Delay::new(lookahead_size(44100.0)),
+            delay_right: Delay::new(lookahead_size(44100.0)),
+            parameters,
+            generation,
         }
     }
 
-    fn init(&mut self) {}
+    fn init(&mut self) {
+        let params = Parameters::from(self.params.as_ref());
+        self.smoothed = SmoothedParameters::new(&self.params, Smoothing::Linear, SMOOTHING_MS);
+        self.allocate(params.sample_rate);
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.params.sample_rate.set(rate);
+        self.params.mark_changed();
+        self.allocate(rate);
+        self.smoothed.set_sample_rate(rate);
+    }
 
     fn get_info(&self) -> Info {
         Info {
@@ -48,6 +183,12 @@ impl Plugin for Stutter {
             inputs: 2,
             // Two channel audio!
             outputs: 2,
+            // For the trigger note
+            midi_inputs: 1,
+            // For mirroring the trigger state
+            midi_outputs: 1,
+            preset_chunks: true,
+            initial_delay: initial_delay(Parameters::from(self.params.as_ref()).latency()),
             // For now, fill in the rest of our fields with `Default` info.
             ..Default::default()
         }
@@ -55,52 +196,305 @@ impl Plugin for Stutter {
 
     fn can_do(&self, can_do: CanDo) -> Supported {
         match can_do {
-            CanDo::Bypass => Supported::Yes,
+            CanDo::ReceiveEvents => Supported::Yes,
+            CanDo::ReceiveMidiEvent => Supported::Yes,
+            CanDo::SendEvents => Supported::Yes,
+            CanDo::SendMidiEvent => Supported::Yes,
+            // This includes Bypass: the host's bypass state never reaches us,
+            // so leave bypassing to the host.
             _ => Supported::No,
         }
     }
 
     // Output audio given the current state of the VST
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        let params = Parameters::from(self.params.as_ref());
-        let wet_dry = params.wet_dry;
+        self.update_params();
+        let params = self.parameters;
+        self.smoothed.update(&self.params);
         let num_samples = buffer.samples();
 
         let (inputs, mut outputs) = buffer.split();
-        let left_input = &inputs[0];
-        let left_output = &mut outputs[0];
 
-        self.ringbuf_left.set_size(params.buffer_size);
-        self.ringbuf_right.set_size(params.buffer_size);
-
-        match (self.last_trigger_state, params.trigger) {
-            // Untriggered -> Triggered
-            (false, true) => {
-                self.ringbuf_left.set_triggered();
-                self.ringbuf_right.set_triggered();
-            }
-            // Triggered -> Untriggered
-            (true, false) => {
-                self.ringbuf_left.set_untriggered();
-                self.ringbuf_right.set_untriggered();
+        // Save Buffer is a one-shot action, so turn it back off once the loop
+        // has been handed off. If the last loop is still being written, try
+        // again next block.
+        if params.save_buffer {
+            let (left, right) = (&self.ringbuf_left, &self.ringbuf_right);
+            let saved = self
+                .exporter
+                .export(params.sample_rate, |left_out, right_out| {
+                    left.copy_loop(left_out);
+                    right.copy_loop(right_out);
+                });
+            if saved {
+                self.params.set_from_audio(0.0, ParameterType::SaveBuffer);
             }
-            _ => (),
         }
 
-        for i in 0..num_samples {
-            let out = self.ringbuf_left.next(left_input[i]);
-            left_output[i] = left_input[i] * (1.0 - wet_dry) + out * wet_dry;
+        let transport = Transport::read(&self.params.host, params.sample_rate);
+        let tempo = transport.tempo;
+        // Keep the tempo around for displaying parameters.
+        #[allow(clippy::float_cmp)]
+        if self.params.tempo.get() != tempo {
+            self.params.tempo.set(tempo);
+            self.params.mark_changed();
         }
+        let quarters_per_sample = transport.quarters_per_sample();
+        // With lookahead, everything runs on the delayed input, so the grid
+        // and pattern follow the position of the delayed input. Triggers
+        // arriving up to the latency late still land on the grid line.
+        let latency = params.latency();
+        let grid = params
+            .quantize
+            .grid(&transport)
+            .map(|(start, step)| (start - latency as f64 * step, step));
+        let lfo_rate = LfoRate::Beats(params.lfo_rate.beats() as f64);
+        // Follow the host's position while the transport runs. Otherwise, keep
+        // counting from wherever the pattern was.
+        if let Some(position) = transport.playing_position() {
+            self.pattern_position = position - latency as f64 * quarters_per_sample;
+            self.lfo.sync(lfo_rate, self.pattern_position);
+        }
+        let step_length = params.pattern_rate.beats() as f64;
+        let (mut buffer_size, synced) = self.loop_size(&params, tempo);
+        // If a synced loop's length changes by more than 1% while triggered,
+        // such as when the tempo changes, crossfade to the new length instead
+        // of jumping.
+        if synced
+            && self.last_trigger_state
+            && buffer_size.abs_diff(self.last_buffer_size) * 100 > self.last_buffer_size
+        {
+            self.ringbuf_left.resize_smoothly();
+            self.ringbuf_right.resize_smoothly();
+        }
+        self.last_buffer_size = buffer_size;
+        let ramping = params.ramp != 0.0;
+        self.ringbuf_left.set_edge_fade(ramping);
+        self.ringbuf_right.set_edge_fade(ramping);
+        // Interpolate the loop length across the block, which is much cheaper
+        // than working out the ramp for every sample. The ramp runs from
+        // `ramp.1` at sample `ramp.0` to `ramp.2` at the end of the block, and
+        // starts over from wherever the loop length changes.
+        let ramp_time = params.ramp_time / 1000.0 * params.sample_rate;
+        let ramp_from = |elapsed: usize, start: usize, buffer_size: usize| {
+            (
+                start,
+                ramp_size(buffer_size, params.ramp, elapsed as f32 / ramp_time),
+                ramp_size(
+                    buffer_size,
+                    params.ramp,
+                    (elapsed + num_samples - start) as f32 / ramp_time,
+                ),
+            )
+        };
+        let mut ramp = ramp_from(self.ramp_elapsed, 0, buffer_size);
+        self.ringbuf_left.set_reverse(params.reverse);
+        self.ringbuf_right.set_reverse(params.reverse);
+        self.ringbuf_left.set_ping_pong(params.ping_pong);
+        self.ringbuf_right.set_ping_pong(params.ping_pong);
+        self.ringbuf_left.set_offset(params.offset);
+        self.ringbuf_right.set_offset(params.offset);
+        self.ringbuf_left.set_snap(params.snap);
+        self.ringbuf_right.set_snap(params.snap);
+        self.ringbuf_left.set_speed(params.speed);
+        self.ringbuf_right.set_speed(params.speed);
+        self.ringbuf_left.set_pitch_step(params.pitch_step);
+        self.ringbuf_right.set_pitch_step(params.pitch_step);
+        self.ringbuf_left.set_slice(params.slices, params.slice);
+        self.ringbuf_right.set_slice(params.slices, params.slice);
+        self.ringbuf_left.set_shuffle(params.shuffle);
+        self.ringbuf_right.set_shuffle(params.shuffle);
+        self.ringbuf_left.set_gate(params.gate_length);
+        self.ringbuf_right.set_gate(params.gate_length);
+        let tape_stop = match params.release {
+            Release::Cut => 0,
+            Release::TapeStop => (params.tape_stop_time / 1000.0 * params.sample_rate) as usize,
+        };
+        self.ringbuf_left.set_tape_stop(tape_stop);
+        self.ringbuf_right.set_tape_stop(tape_stop);
+        self.ringbuf_left.set_preroll(params.preroll);
+        self.ringbuf_right.set_preroll(params.preroll);
+        self.ringbuf_left.set_voice_count(params.voices);
+        self.ringbuf_right.set_voice_count(params.voices);
+        self.ringbuf_left.set_max_repeats(params.repeats);
+        self.ringbuf_right.set_max_repeats(params.repeats);
+        self.ringbuf_left
+            .set_overdub(params.overdub, params.feedback);
+        self.ringbuf_right
+            .set_overdub(params.overdub, params.feedback);
+
+        let ms_to_samples = |ms: f32| (ms / 1000.0 * params.sample_rate) as usize;
+        self.threshold_trigger
+            .set_times(ms_to_samples(params.hold), ms_to_samples(params.rearm));
 
-        let right_input = &inputs[1];
-        let right_output = &mut outputs[1];
+        let slot_length = params.auto_rate.beats() as f64;
 
+        let midi_in = std::mem::take(&mut self.midi_in);
+        let mut next_message = 0;
         for i in 0..num_samples {
-            let out = self.ringbuf_right.next(right_input[i]);
-            right_output[i] = right_input[i] * (1.0 - wet_dry) + out * wet_dry;
+            // Apply the MIDI messages for this sample. Notes can change the
+            // loop length, which restarts the ramp from here.
+            let mut changed = false;
+            while let Some(&TimedMessage { message, .. }) =
+                midi_in.get(next_message).filter(|event| event.offset <= i)
+            {
+                self.handle_message(message, &params);
+                next_message += 1;
+                changed = true;
+            }
+            if changed {
+                buffer_size = self.loop_size(&params, tempo).0;
+                ramp = ramp_from(self.ramp_elapsed, i, buffer_size);
+            }
+
+            // At the start of every slot, roll to see if the slot triggers.
+            // Like the pattern's steps, the slots follow the host's position,
+            // so they stay on the grid when the host loops or jumps.
+            let mut retrigger = std::mem::take(&mut self.note_retrigger);
+            if params.probability > 0.0 {
+                let slot = swung_step(self.pattern_position, slot_length, params.swing as f64);
+                if self.auto_slot != Some(slot) {
+                    self.auto_slot = Some(slot);
+                    self.auto_trigger = self.rng.next_f32() < params.probability;
+                    retrigger = self.auto_trigger;
+                }
+            } else {
+                self.auto_trigger = false;
+                self.auto_slot = None;
+            }
+
+            // Manual triggers only take effect once the playhead crosses a grid
+            // line.
+            let on_grid = match grid {
+                Some((start, step)) => {
+                    let position = start + i as f64 * step;
+                    position.floor() != (position - step).floor()
+                }
+                None => true,
+            };
+            if on_grid {
+                self.manual_trigger = params.trigger || self.midi_trigger || self.cc_trigger;
+            }
+
+            let left = self.delay_left.next(inputs[0][i], latency);
+            let right = self.delay_right.next(inputs[1][i], latency);
+            if let Some(threshold) = params.threshold {
+                let level = left.abs().max(right.abs());
+                if self.threshold_trigger.next(level, db_to_gain(threshold)) {
+                    retrigger = true;
+                }
+            } else {
+                self.threshold_trigger.reset();
+            }
+
+            // Each step of the pattern which is on retriggers the stutter.
+            if params.pattern {
+                let step = swung_step(self.pattern_position, step_length, params.swing as f64);
+                if self.pattern_step != Some(step) {
+                    self.pattern_step = Some(step);
+                    let index = step.rem_euclid(PATTERN_STEPS as i64) as usize;
+                    self.pattern_trigger = params.step(index);
+                    retrigger |= self.pattern_trigger;
+                }
+            } else {
+                self.pattern_trigger = false;
+                self.pattern_step = None;
+            }
+
+            // The LFO holds the trigger for the part of each cycle it spends
+            // above the threshold, firing again at the start of each cycle.
+            if params.lfo_depth > 0.0 {
+                let value = self.lfo.advance(params.lfo_shape, lfo_rate, &transport);
+                let lfo_trigger = value >= 1.0 - params.lfo_depth;
+                retrigger |= lfo_trigger && (self.lfo.new_cycle() || !self.lfo_trigger);
+                self.lfo_trigger = lfo_trigger;
+            } else {
+                self.lfo_trigger = false;
+                self.lfo.reset();
+            }
+            self.pattern_position += quarters_per_sample;
+
+            let trigger = self.manual_trigger
+                || self.auto_trigger
+                || self.threshold_trigger.engaged
+                || self.pattern_trigger
+                || self.lfo_trigger;
+            let restart = trigger && (!self.last_trigger_state || retrigger);
+            match (self.last_trigger_state, trigger) {
+                // Untriggered -> Triggered
+                (false, true) => self.set_triggered(),
+                // A slot triggered while already triggered, so start over
+                (true, true) if retrigger => self.set_triggered(),
+                // Triggered -> Untriggered
+                (true, false) => self.set_untriggered(),
+                _ => (),
+            }
+            self.last_trigger_state = trigger;
+
+            // Restart the ramp along with the loop, from this sample rather
+            // than from wherever it was at the start of the block.
+            if restart {
+                ramp = ramp_from(0, i, buffer_size);
+            }
+            if trigger {
+                let (start, from, to) = ramp;
+                let t = (i - start) as f32 / (num_samples - start) as f32;
+                let size = from + (to - from) * t;
+                let size = (size.round() as usize).clamp(1, params.max_buffer_size);
+                self.set_size(size, &params);
+                self.ramp_elapsed += 1;
+            }
+
+            self.smoothed.advance();
+            let wet_dry = self.smoothed.get(ParameterType::WetDry);
+
+            let left_out = self.ringbuf_left.next(left);
+            let right_out = self.ringbuf_right.next(right);
+
+            // Mirror the trigger, and optionally each repeat, as a MIDI note.
+            if params.midi_out != MidiOut::Off {
+                let repeats = self.ringbuf_left.repeats();
+                let wrapped =
+                    params.midi_out == MidiOut::Repeats && repeats > self.note_out.repeats;
+                self.note_out.repeats = repeats;
+                if restart || (trigger && wrapped) {
+                    let channel = params.trigger_channel.unwrap_or(0);
+                    self.note_out.note_on(i, params.output_note, channel);
+                } else if !trigger {
+                    self.note_out.note_off(i);
+                }
+            }
+            // Follow the trigger's crossfade so that the dry level doesn't
+            // jump.
+            let dry_gain = 1.0 + (params.dry_gain() - 1.0) * self.ringbuf_left.engaged();
+            let dry = (1.0 - wet_dry) * dry_gain;
+            outputs[0][i] = left * dry + left_out * wet_dry;
+            outputs[1][i] = right * dry + right_out * wet_dry;
         }
 
-        self.last_trigger_state = params.trigger;
+        // Messages timed past the end of the block still take effect.
+        for &TimedMessage { message, .. } in &midi_in[next_message..] {
+            self.handle_message(message, &params);
+        }
+        self.midi_in = midi_in;
+        self.midi_in.clear();
+
+        if params.midi_out == MidiOut::Off {
+            self.note_out.note_off(0);
+        }
+        let mut host = self.params.host;
+        self.note_out.send(&mut host);
+    }
+
+    // Hosts send the events for a block just before processing it, in order.
+    // They are queued here and applied at their own samples by `process`.
+    fn process_events(&mut self, events: &Events) {
+        for message in self.midi_parser.messages(events) {
+            // Drop messages rather than allocate on the audio thread.
+            if self.midi_in.len() < MAX_MIDI_IN_EVENTS {
+                self.midi_in.push(message);
+            }
+        }
     }
 
     // The raw parameters exposed to the host
@@ -109,94 +503,1133 @@ impl Plugin for Stutter {
     }
 }
 
-struct RingBuffer {
-    buffer: [f32; MAX_BUFFER_SIZE],
-    // The index of the "next" sample to be played.
-    needle: usize,
-    // The maximum index the needle may take on.
-    size: usize,
-    trigger: bool,
-}
-
-impl RingBuffer {
-    fn new(size: usize) -> RingBuffer {
-        RingBuffer {
-            buffer: [0.0; MAX_BUFFER_SIZE],
-            needle: 0,
-            size,
-            trigger: false,
+impl Stutter {
+    /// Rebuild the parameters if any have changed since they were last built.
+    fn update_params(&mut self) {
+        let generation = self.params.generation();
+        if generation != self.generation {
+            self.generation = generation;
+            self.parameters = Parameters::from(self.params.as_ref());
         }
     }
 
-    // Return the next sample from the ring buffer, optionally also consuming a
-    // sample in the process.
-    fn next(&mut self, input: f32) -> f32 {
-        if self.trigger {
-            // If the needle hasn't been through the entire buffer yet, write
-            // the input. This allows `size` to increase and play the audio that
-            // "would have" been there if size was larger initially.
-            if self.needle < MAX_BUFFER_SIZE {
-                self.buffer[self.needle] = input;
+    /// Respond to a MIDI message from the host.
+    fn handle_message(&mut self, message: MidiMessage, params: &Parameters) {
+        if params
+            .trigger_channel
+            .is_some_and(|x| x != message.channel())
+        {
+            return;
+        }
+        match message {
+            // With Key Track on, every note triggers, and the latest note
+            // sets the loop length.
+            MidiMessage::NoteOn { note, .. } if params.key_track => {
+                self.key_note = Some(note);
+                self.note_retrigger = self.midi_trigger;
+                self.midi_trigger = true;
+            }
+            MidiMessage::NoteOff { note, .. }
+                if params.key_track && self.key_note == Some(note) =>
+            {
+                self.midi_trigger = false
+            }
+            MidiMessage::NoteOff { .. } if params.key_track => (),
+            // With Note Divisions on, notes around the trigger note pick
+            // the loop length, and the latest held note wins.
+            MidiMessage::NoteOn { note, .. } if params.note_divisions => {
+                self.hold_note(note, true, params)
+            }
+            MidiMessage::NoteOff { note, .. } if params.note_divisions => {
+                self.hold_note(note, false, params)
+            }
+            MidiMessage::NoteOn { note, .. } if note == params.trigger_note => {
+                self.midi_trigger = true
             }
+            MidiMessage::NoteOff { note, .. } if note == params.trigger_note => {
+                self.midi_trigger = false
+            }
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } if Some(controller) == params.trigger_cc => self.cc_trigger = value >= 64,
+            // CC input controls the buffer size directly, without being
+            // recorded as automation.
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } if Some(controller) == params.buffer_size_cc => self
+                .params
+                .set_silently(value as f32 / 127.0, ParameterType::BufferSize),
+            _ => (),
+        }
+    }
 
-            let sample = self.buffer[self.needle % self.size];
-            self.needle += 1;
-            sample
+    /// The length of the loop, before ramping, and whether it follows the
+    /// tempo.
+    fn loop_size(&self, params: &Parameters, tempo: f32) -> (usize, bool) {
+        // Synced loop lengths follow the tempo.
+        if let Some(note) = self.key_note.filter(|_| params.key_track) {
+            // One repeat per cycle of the note's pitch.
+            let frequency = 440.0 * 2.0f32.powf((note as f32 - 69.0) / 12.0);
+            let samples = params.sample_rate / frequency;
+            (
+                (samples.round() as usize).clamp(1, params.max_buffer_size),
+                false,
+            )
+        } else if let Some(division) = self
+            .held_notes
+            .last()
+            .filter(|_| params.note_divisions)
+            .and_then(|&note| note_division(note, params.trigger_note))
+        {
+            let samples = division.samples(tempo, params.sample_rate);
+            (
+                (samples.round() as usize).clamp(1, params.max_buffer_size),
+                true,
+            )
+        } else if params.sync {
+            let samples = params.division.samples(tempo, params.sample_rate);
+            (
+                (samples.round() as usize).clamp(1, params.max_buffer_size),
+                true,
+            )
         } else {
-            input
+            (params.buffer_size, false)
         }
     }
 
-    fn set_size(&mut self, new_size: usize) {
-        self.size = new_size;
+    /// Press or release `note` while Note Divisions is on. Notes which don't
+    /// map to a division are ignored.
+    fn hold_note(&mut self, note: u8, on: bool, params: &Parameters) {
+        if note_division(note, params.trigger_note).is_none() {
+            return;
+        }
+        let last = self.held_notes.last().copied();
+        self.held_notes.retain(|&held| held != note);
+        if on {
+            self.held_notes.push(note);
+        }
+        let held = self.held_notes.last().copied();
+        self.midi_trigger = held.is_some();
+        // Restart the loop whenever another held note takes over.
+        self.note_retrigger |= last.is_some() && held.is_some() && last != held;
+    }
+
+    /// Size the buffers for the longest loop at `sample_rate`.
+    fn allocate(&mut self, sample_rate: f32) {
+        let capacity = max_buffer_size(sample_rate);
+        self.ringbuf_left.set_capacity(capacity);
+        self.ringbuf_right.set_capacity(capacity);
+        self.ringbuf_left.set_sample_rate(sample_rate);
+        self.ringbuf_right.set_sample_rate(sample_rate);
+        self.exporter.set_capacity(capacity);
+        self.delay_left = Delay::new(lookahead_size(sample_rate));
+        self.delay_right = Delay::new(lookahead_size(sample_rate));
     }
 
     fn set_triggered(&mut self) {
-        self.needle = 0;
-        self.trigger = true;
+        self.ramp_elapsed = 0;
+        self.ringbuf_left.set_triggered();
+        self.ringbuf_right.set_triggered();
     }
 
     fn set_untriggered(&mut self) {
-        self.trigger = false;
+        self.ringbuf_left.set_untriggered();
+        self.ringbuf_right.set_untriggered();
+    }
+
+    /// Set the loop length of the main voice, and of the extra voices relative
+    /// to it. The right channel's loop is scaled by Right Length.
+    fn set_size(&mut self, size: usize, params: &Parameters) {
+        let right_size = (size as f32 * params.right_length).round() as usize;
+        for (ringbuf, size) in [
+            (&mut self.ringbuf_left, size),
+            (
+                &mut self.ringbuf_right,
+                right_size.clamp(1, params.max_buffer_size),
+            ),
+        ] {
+            ringbuf.set_size(size);
+            for index in 1..params.voices {
+                let (length, level) = params.voice(index);
+                ringbuf.set_voice(index, length.of(size), level);
+            }
+        }
     }
 }
 
+/// Collects the MIDI notes mirroring the trigger state, to send to the host
+/// at the end of each block.
+struct NoteOut {
+    events: Vec<MidiEvent>,
+    send_buffer: SendEventBuffer,
+    // The note and channel currently on, if any.
+    sounding: Option<(u8, u8)>,
+    // The number of repeats of the loop as of the last sample.
+    repeats: usize,
+}
+
+impl NoteOut {
+    fn new() -> NoteOut {
+        NoteOut {
+            events: Vec::with_capacity(MAX_MIDI_OUT_EVENTS),
+            send_buffer: SendEventBuffer::new(MAX_MIDI_OUT_EVENTS),
+            sounding: None,
+            repeats: 0,
+        }
+    }
+
+    /// Start `note` on `channel` at sample `delta` of the block, ending the
+    /// note already on, if any.
+    fn note_on(&mut self, delta: usize, note: u8, channel: u8) {
+        self.note_off(delta);
+        self.push(delta, [0x90 | channel, note, 100]);
+        self.sounding = Some((note, channel));
+    }
+
+    /// End the note currently on, if any, at sample `delta` of the block.
+    fn note_off(&mut self, delta: usize) {
+        if let Some((note, channel)) = self.sounding.take() {
+            self.push(delta, [0x80 | channel, note, 0]);
+        }
+    }
+
+    fn push(&mut self, delta: usize, data: [u8; 3]) {
+        // Drop events rather than allocate on the audio thread.
+        if self.events.len() < MAX_MIDI_OUT_EVENTS {
+            self.events.push(MidiEvent {
+                data,
+                delta_frames: delta as i32,
+                live: true,
+                note_length: None,
+                note_offset: None,
+                detune: 0,
+                note_off_velocity: 0,
+            });
+        }
+    }
+
+    /// Send the events collected this block to the host.
+    fn send(&mut self, host: &mut dyn Host) {
+        if !self.events.is_empty() {
+            self.send_buffer.send_events(self.events.drain(..), host);
+        }
+    }
+}
+
+/// Engages the stutter whenever the input gets loud enough, such as on a drum
+/// hit. Once fired, the trigger holds for a set time, then waits for a set
+/// time before it can fire again.
+struct ThresholdTrigger {
+    engaged: bool,
+    // The number of samples left to hold, if engaged, or left until re-armed,
+    // if not.
+    clock: usize,
+    hold: usize,
+    rearm: usize,
+}
+
+impl ThresholdTrigger {
+    fn new() -> ThresholdTrigger {
+        ThresholdTrigger {
+            engaged: false,
+            clock: 0,
+            hold: 0,
+            rearm: 0,
+        }
+    }
+
+    fn set_times(&mut self, hold: usize, rearm: usize) {
+        self.hold = hold;
+        self.rearm = rearm;
+    }
+
+    fn reset(&mut self) {
+        self.engaged = false;
+        self.clock = 0;
+    }
+
+    // Advance by one sample. Returns true if the trigger fired.
+    fn next(&mut self, level: f32, threshold: f32) -> bool {
+        if self.clock > 0 {
+            self.clock -= 1;
+        } else if self.engaged {
+            self.engaged = false;
+            self.clock = self.rearm;
+        } else if level >= threshold {
+            self.engaged = true;
+            self.clock = self.hold;
+            return true;
+        }
+        false
+    }
+}
+
+#[derive(Clone, Copy)]
 struct Parameters {
     trigger: bool,
     buffer_size: usize,
-    wet_dry: f32,
+    /// The MIDI note which engages the stutter while held
+    trigger_note: u8,
+    /// The MIDI channel (0-15) to listen on, or None to listen on all channels
+    trigger_channel: Option<u8>,
+    /// The MIDI CC which controls Trigger, if any
+    trigger_cc: Option<u8>,
+    /// The MIDI CC which controls Buffer Size, if any
+    buffer_size_cc: Option<u8>,
+    /// If true, the buffer size follows the host tempo instead of Buffer Size
+    sync: bool,
+    division: Division,
+    reverse: bool,
+    /// If true, every other repeat plays in the opposite direction
+    ping_pong: bool,
+    /// How far into the captured loop playback starts, as a fraction of the
+    /// loop length
+    offset: f32,
+    /// If true, the loop points are moved to the nearest zero crossings
+    snap: bool,
+    /// The playback speed of the loop, from 0.25x to 4x
+    speed: f32,
+    /// The change in pitch of each repeat over the last, in semitones
+    pitch_step: f32,
+    /// The length of each automatic trigger slot
+    auto_rate: Division,
+    /// The chance that each automatic trigger slot triggers, or 0.0 if
+    /// automatic triggering is off
+    probability: f32,
+    quantize: Quantize,
+    /// If true, the input is delayed so that quantized triggers arriving
+    /// shortly after a grid line still land on it
+    lookahead: bool,
+    /// How much the loop length shrinks (negative) or grows (positive) while
+    /// triggered, from -1.0 to 1.0
+    ramp: f32,
+    /// The length of the ramp, in milliseconds
+    ramp_time: f32,
+    /// The number of equal slices the loop is divided into
+    slices: usize,
+    /// The index of the slice which repeats
+    slice: usize,
+    /// If true, each repeat plays a random slice
+    shuffle: bool,
+    /// The fraction of each repeat which plays before being gated off
+    gate_length: f32,
+    /// The input level, in decibels, which triggers the stutter, or None if
+    /// the input level doesn't trigger it
+    threshold: Option<f32>,
+    /// How long a threshold trigger lasts, in milliseconds
+    hold: f32,
+    /// How long after a threshold trigger ends before the next can fire, in
+    /// milliseconds
+    rearm: f32,
+    /// If true, the step pattern triggers the stutter
+    pattern: bool,
+    /// The length of each step of the pattern
+    pattern_rate: Division,
+    step_1: bool,
+    step_2: bool,
+    step_3: bool,
+    step_4: bool,
+    step_5: bool,
+    step_6: bool,
+    step_7: bool,
+    step_8: bool,
+    step_9: bool,
+    step_10: bool,
+    step_11: bool,
+    step_12: bool,
+    step_13: bool,
+    step_14: bool,
+    step_15: bool,
+    step_16: bool,
+    /// The number of repeats to play before going back to the input, or None
+    /// to repeat for as long as triggered
+    repeats: Option<usize>,
+    /// If true, the loop keeps recording the input while it repeats
+    overdub: bool,
+    /// How much of the existing loop is kept when overdubbing
+    feedback: f32,
+    release: Release,
+    /// If true, triggering loops the audio from just before the trigger
+    preroll: bool,
+    /// How long the tape stop takes, in milliseconds
+    tape_stop_time: f32,
+    dry_mode: DryMode,
+    /// How far the dry signal is ducked while triggered, in decibels
+    duck: f32,
+    /// How much of each retrigger LFO cycle is spent triggered, or 0.0 if the
+    /// LFO is off
+    lfo_depth: f32,
+    /// The length of each retrigger LFO cycle
+    lfo_rate: Division,
+    lfo_shape: LfoShape,
+    /// If true, the captured loop is saved to a WAV file
+    save_buffer: bool,
+    /// If true, every MIDI note triggers the stutter and sets the loop length
+    /// to one cycle of the note's pitch
+    key_track: bool,
+    /// If true, notes around the trigger note trigger the stutter and select
+    /// the loop's note division
+    note_divisions: bool,
+    /// How far every other automatic slot and pattern step is delayed, as a
+    /// fraction of its length, from 0.0 to 0.75
+    swing: f32,
+    /// The length of the right channel's loop relative to the left's, from
+    /// 0.5x to 2x
+    right_length: f32,
+    midi_out: MidiOut,
+    /// The MIDI note sent to mirror the trigger state
+    output_note: u8,
+    /// The number of voices playing the loop, from 1 to MAX_VOICES
+    voices: usize,
+    voice_2_length: VoiceLength,
+    voice_2_level: f32,
+    voice_3_length: VoiceLength,
+    voice_3_level: f32,
+    voice_4_length: VoiceLength,
+    voice_4_level: f32,
+    sample_rate: f32,
+    /// The longest loop, in samples, at the current sample rate
+    max_buffer_size: usize,
+    /// The host's tempo, or None if the host hasn't reported one
+    tempo: Option<f32>,
+}
+
+impl Parameters {
+    /// The delay, in samples, between the input and the output. This is only
+    /// nonzero while quantized triggers look ahead.
+    fn latency(&self) -> usize {
+        if self.lookahead && self.quantize != Quantize::Off {
+            lookahead_size(self.sample_rate)
+        } else {
+            0
+        }
+    }
+
+    /// The gain applied to the dry signal while triggered.
+    fn dry_gain(&self) -> f32 {
+        match self.dry_mode {
+            DryMode::Mix => 1.0,
+            DryMode::Mute => 0.0,
+            DryMode::Duck => db_to_gain(self.duck),
+        }
+    }
+
+    /// Returns true if step `index` of the step pattern is on.
+    fn step(&self, index: usize) -> bool {
+        [
+            self.step_1,
+            self.step_2,
+            self.step_3,
+            self.step_4,
+            self.step_5,
+            self.step_6,
+            self.step_7,
+            self.step_8,
+            self.step_9,
+            self.step_10,
+            self.step_11,
+            self.step_12,
+            self.step_13,
+            self.step_14,
+            self.step_15,
+            self.step_16,
+        ][index]
+    }
+
+    /// Returns the length and level of extra voice `index`, from 1 to 3.
+    fn voice(&self, index: usize) -> (VoiceLength, f32) {
+        match index {
+            1 => (self.voice_2_length, self.voice_2_level),
+            2 => (self.voice_3_length, self.voice_3_level),
+            _ => (self.voice_4_length, self.voice_4_level),
+        }
+    }
 }
 
 impl From<&RawParameters> for Parameters {
     fn from(params: &RawParameters) -> Self {
+        let max_buffer_size = max_buffer_size(params.sample_rate.get());
+        let slices = params.real(ParameterType::Slices) as usize;
         Parameters {
-            wet_dry: params.wet_dry.get(),
-            buffer_size: ((ease_in_expo(params.buffer_size.get()) * MAX_BUFFER_SIZE as f32)
+            buffer_size: ((params.real(ParameterType::BufferSize) * max_buffer_size as f32)
                 as usize)
-                .clamp(1, MAX_BUFFER_SIZE),
+                .clamp(1, max_buffer_size),
             trigger: params.trigger.get() > 0.5,
+            trigger_note: params.real(ParameterType::TriggerNote) as u8,
+            trigger_channel: match params.real(ParameterType::TriggerChannel) as u8 {
+                0 => None,
+                x => Some(x - 1),
+            },
+            trigger_cc: cc_number(params.real(ParameterType::TriggerCc) as usize),
+            buffer_size_cc: cc_number(params.real(ParameterType::BufferSizeCc) as usize),
+            sync: params.sync.get() > 0.5,
+            division: Division::from(params.division.get()),
+            reverse: params.reverse.get() > 0.5,
+            ping_pong: params.ping_pong.get() > 0.5,
+            offset: params.offset.get(),
+            snap: params.snap.get() > 0.5,
+            speed: params.real(ParameterType::Speed),
+            pitch_step: params.real(ParameterType::PitchStep),
+            auto_rate: Division::from(params.auto_rate.get()),
+            probability: params.probability.get(),
+            quantize: Quantize::from(params.quantize.get()),
+            lookahead: params.lookahead.get() > 0.5,
+            ramp: params.real(ParameterType::Ramp) / 100.0,
+            ramp_time: params.real(ParameterType::RampTime),
+            slices,
+            slice: (params.real(ParameterType::Slice) as usize).min(slices) - 1,
+            shuffle: params.shuffle.get() > 0.5,
+            gate_length: params.gate_length.get(),
+            threshold: if params.threshold.get() > 0.0 {
+                Some(params.real(ParameterType::Threshold))
+            } else {
+                None
+            },
+            hold: params.real(ParameterType::Hold),
+            rearm: params.real(ParameterType::Rearm),
+            pattern: params.pattern.get() > 0.5,
+            pattern_rate: Division::from(params.pattern_rate.get()),
+            step_1: params.step_1.get() > 0.5,
+            step_2: params.step_2.get() > 0.5,
+            step_3: params.step_3.get() > 0.5,
+            step_4: params.step_4.get() > 0.5,
+            step_5: params.step_5.get() > 0.5,
+            step_6: params.step_6.get() > 0.5,
+            step_7: params.step_7.get() > 0.5,
+            step_8: params.step_8.get() > 0.5,
+            step_9: params.step_9.get() > 0.5,
+            step_10: params.step_10.get() > 0.5,
+            step_11: params.step_11.get() > 0.5,
+            step_12: params.step_12.get() > 0.5,
+            step_13: params.step_13.get() > 0.5,
+            step_14: params.step_14.get() > 0.5,
+            step_15: params.step_15.get() > 0.5,
+            step_16: params.step_16.get() > 0.5,
+            repeats: repeat_count(params.real(ParameterType::Repeats) as usize),
+            overdub: params.overdub.get() > 0.5,
+            feedback: params.feedback.get(),
+            release: Release::from(params.release.get()),
+            preroll: params.preroll.get() > 0.5,
+            tape_stop_time: params.real(ParameterType::TapeStopTime),
+            dry_mode: DryMode::from(params.dry_mode.get()),
+            duck: params.real(ParameterType::Duck),
+            lfo_depth: params.lfo_depth.get(),
+            lfo_rate: Division::from(params.lfo_rate.get()),
+            lfo_shape: LfoShape::from(params.lfo_shape.get()),
+            save_buffer: params.save_buffer.get() > 0.5,
+            key_track: params.key_track.get() > 0.5,
+            note_divisions: params.note_divisions.get() > 0.5,
+            swing: params.real(ParameterType::Swing) / 100.0,
+            midi_out: MidiOut::from(params.midi_out.get()),
+            output_note: params.real(ParameterType::OutputNote) as u8,
+            right_length: params.real(ParameterType::RightLength),
+            voices: params.real(ParameterType::Voices) as usize,
+            voice_2_length: VoiceLength::from(params.voice_2_length.get()),
+            voice_2_level: params.voice_2_level.get(),
+            voice_3_length: VoiceLength::from(params.voice_3_length.get()),
+            voice_3_level: params.voice_3_level.get(),
+            voice_4_length: VoiceLength::from(params.voice_4_length.get()),
+            voice_4_level: params.voice_4_level.get(),
+            sample_rate: params.sample_rate.get(),
+            max_buffer_size,
+            tempo: Some(params.tempo.get()).filter(|&tempo| tempo > 0.0),
+        }
+    }
+}
+
+/// Returns the longest loop, in samples, at `sample_rate`.
+fn max_buffer_size(sample_rate: f32) -> usize {
+    (MAX_BUFFER_SECONDS * sample_rate) as usize
+}
+
+/// Returns the lookahead, in samples, at `sample_rate`.
+fn lookahead_size(sample_rate: f32) -> usize {
+    (LOOKAHEAD_MS / 1000.0 * sample_rate) as usize
+}
+
+/// Returns the loop length, in samples, `progress` of the way through a ramp.
+/// A `ramp` of -1.0 shrinks the loop to 1/16th of its size by the end of the
+/// ramp, while a `ramp` of 1.0 grows it to 16 times its size.
+fn ramp_size(size: usize, ramp: f32, progress: f32) -> f32 {
+    size as f32 * 16.0f32.powf(ramp * progress.min(1.0))
+}
+
+/// Returns the index of the step that `position` falls in, for steps `length`
+/// long. `swing` delays the start of every odd step by that fraction of a
+/// step.
+fn swung_step(position: f64, length: f64, swing: f64) -> i64 {
+    let pair = (position / (2.0 * length)).floor();
+    let offset = position - pair * 2.0 * length;
+    pair as i64 * 2 + (offset >= length * (1.0 + swing)) as i64
+}
+
+/// Which grid lines manual triggers wait for before taking effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quantize {
+    Off,
+    Sixteenth,
+    Beat,
+    Bar,
+}
+
+impl Quantize {
+    /// Returns the position of the first sample of the block on the grid,
+    /// measured in grid steps, and how many grid steps each sample advances.
+    /// Returns None if triggers shouldn't wait, either because quantizing is
+    /// off or because the host transport isn't running.
+    fn grid(&self, transport: &Transport) -> Option<(f64, f64)> {
+        // The grid step, in quarter notes
+        let (step, origin) = match self {
+            Quantize::Off => return None,
+            Quantize::Sixteenth => (0.25, 0.0),
+            Quantize::Beat => (transport.beat_length(), 0.0),
+            Quantize::Bar => (transport.bar_length(), transport.bar_start),
+        };
+        transport.steps(step, origin)
+    }
+}
+
+impl From<f32> for Quantize {
+    fn from(x: f32) -> Self {
+        match (x * 4.0) as usize {
+            0 => Quantize::Off,
+            1 => Quantize::Sixteenth,
+            2 => Quantize::Beat,
+            _ => Quantize::Bar,
+        }
+    }
+}
+
+impl std::fmt::Display for Quantize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quantize::Off => write!(f, "Off"),
+            Quantize::Sixteenth => write!(f, "1/16"),
+            Quantize::Beat => write!(f, "Beat"),
+            Quantize::Bar => write!(f, "Bar"),
+        }
+    }
+}
+
+/// What happens to the loop when the trigger is released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Release {
+    /// Fade straight back to the input.
+    Cut,
+    /// Slow the loop down to a stop, pitching it down, then fade back.
+    TapeStop,
+}
+
+impl From<f32> for Release {
+    fn from(x: f32) -> Self {
+        if x < 0.5 {
+            Release::Cut
+        } else {
+            Release::TapeStop
+        }
+    }
+}
+
+impl std::fmt::Display for Release {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Release::Cut => write!(f, "Cut"),
+            Release::TapeStop => write!(f, "Tape Stop"),
+        }
+    }
+}
+
+/// What happens to the dry signal while triggered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DryMode {
+    /// Mix the dry signal in according to Wet/Dry, the same as untriggered.
+    Mix,
+    /// Remove the dry signal entirely.
+    Mute,
+    /// Turn the dry signal down by the Duck amount.
+    Duck,
+}
+
+impl From<f32> for DryMode {
+    fn from(x: f32) -> Self {
+        match (x * 3.0) as usize {
+            0 => DryMode::Mix,
+            1 => DryMode::Mute,
+            _ => DryMode::Duck,
         }
     }
 }
 
-macro_rules! table {
-    ($macro:ident) => {
-        $macro! {
-        //  RawParameter identifier, ParameterType identifier
-            RawParameters,          ParameterType;
-        //  variant      field_name    name             idx   default    strings
-            WetDry,      wet_dry,      "Wet/Dry",       0,    1.0,       |x: f32| make_strings(x * 100.0, "%");
-            Trigger,     trigger,      "Trigger",       1,    0.0,       |x: bool| if x {("ON".to_string(), "".to_string())} else {("OFF".to_string(), "".to_string())};
-            BufferSize,  buffer_size,  "Buffer Size",   2,    0.5,       |x: usize| (format!("{}", x), "Samples".to_string());
+impl std::fmt::Display for DryMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DryMode::Mix => write!(f, "Mix"),
+            DryMode::Mute => write!(f, "Mute"),
+            DryMode::Duck => write!(f, "Duck"),
+        }
+    }
+}
+
+/// Whether a note division is straight, dotted, or a triplet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Feel {
+    Straight,
+    Dotted,
+    Triplet,
+}
+
+/// A musical note length, such as a dotted eighth note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Division {
+    /// The length as a fraction of a whole note, such as 8 for an eighth note
+    denominator: u32,
+    feel: Feel,
+}
+
+impl Division {
+    /// The number of straight divisions, from 1/1 down to 1/64.
+    const STRAIGHT_COUNT: usize = 7;
+    /// The number of divisions, counting dotted and triplet divisions.
+    const COUNT: usize = Division::STRAIGHT_COUNT * 3;
+
+    /// Returns the division at `i`, where the divisions are ordered from
+    /// longest to shortest note value, and each note value is ordered straight,
+    /// dotted, then triplet.
+    fn from_index(i: usize) -> Division {
+        let feel = match i % 3 {
+            0 => Feel::Straight,
+            1 => Feel::Dotted,
+            _ => Feel::Triplet,
+        };
+        Division {
+            denominator: 1 << (i / 3),
+            feel,
+        }
+    }
+
+    /// Returns the division closest in length to `samples`.
+    fn nearest(samples: f32, tempo: f32, sample_rate: f32) -> Division {
+        let distance =
+            |division: &Division| (division.samples(tempo, sample_rate) / samples).ln().abs();
+        (0..Division::COUNT)
+            .map(Division::from_index)
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .unwrap()
+    }
+
+    /// The length of the division, in samples.
+    fn samples(&self, tempo: f32, sample_rate: f32) -> f32 {
+        self.beats() * 60.0 / tempo * sample_rate
+    }
+
+    /// The length of the division, in quarter note beats.
+    fn beats(&self) -> f32 {
+        let beats = 4.0 / self.denominator as f32;
+        match self.feel {
+            Feel::Straight => beats,
+            Feel::Dotted => beats * 1.5,
+            Feel::Triplet => beats * 2.0 / 3.0,
+        }
+    }
+}
+
+impl From<f32> for Division {
+    fn from(x: f32) -> Self {
+        let count = Division::COUNT;
+        Division::from_index(((x * count as f32) as usize).min(count - 1))
+    }
+}
+
+impl std::fmt::Display for Division {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.feel {
+            Feel::Straight => write!(f, "1/{}", self.denominator),
+            Feel::Dotted => write!(f, "1/{}.", self.denominator),
+            Feel::Triplet => write!(f, "1/{}T", self.denominator),
+        }
+    }
+}
+
+/// Which MIDI notes are sent to mirror the trigger state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MidiOut {
+    Off,
+    /// A note which is on while triggered.
+    Trigger,
+    /// A note which is struck again at the start of every repeat.
+    Repeats,
+}
+
+impl From<f32> for MidiOut {
+    fn from(x: f32) -> Self {
+        match (x * 3.0) as usize {
+            0 => MidiOut::Off,
+            1 => MidiOut::Trigger,
+            _ => MidiOut::Repeats,
         }
+    }
+}
+
+impl std::fmt::Display for MidiOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MidiOut::Off => write!(f, "Off"),
+            MidiOut::Trigger => write!(f, "Trigger"),
+            MidiOut::Repeats => write!(f, "Repeats"),
+        }
+    }
+}
+
+/// The loop length of an extra voice, as a ratio of the main loop length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct VoiceLength {
+    numerator: usize,
+    denominator: usize,
+}
+
+impl VoiceLength {
+    const ALL: [(usize, usize); 9] = [
+        (1, 4),
+        (1, 3),
+        (1, 2),
+        (2, 3),
+        (3, 4),
+        (1, 1),
+        (4, 3),
+        (3, 2),
+        (2, 1),
+    ];
+
+    /// Returns the length, in samples, of a voice playing against a main loop
+    /// `size` samples long.
+    fn of(&self, size: usize) -> usize {
+        size * self.numerator / self.denominator
+    }
+}
+
+impl From<f32> for VoiceLength {
+    fn from(x: f32) -> Self {
+        let count = VoiceLength::ALL.len();
+        let (numerator, denominator) =
+            VoiceLength::ALL[((x * count as f32) as usize).min(count - 1)];
+        VoiceLength {
+            numerator,
+            denominator,
+        }
+    }
+}
+
+impl std::fmt::Display for VoiceLength {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}x", self.numerator, self.denominator)
+    }
+}
+
+/// Returns the text for the Buffer Size parameter, which includes the length
+/// in milliseconds and, if the tempo is known, the nearest note division.
+fn buffer_size_string(samples: usize, params: &Parameters) -> (String, String) {
+    let ms = samples as f32 / params.sample_rate * 1000.0;
+    let text = match params.tempo {
+        Some(tempo) => {
+            let division = Division::nearest(samples as f32, tempo, params.sample_rate);
+            format!(
+                "{} smp / {:.0} ms / ~{} @ {:.0} BPM",
+                samples, ms, division, tempo
+            )
+        }
+        None => format!("{} smp / {:.0} ms", samples, ms),
     };
+    (text, "".to_string())
+}
+
+/// Returns the MIDI CC number selected by option `index` of a CC parameter,
+/// where option 0 is no CC at all.
+fn cc_number(index: usize) -> Option<u8> {
+    match index {
+        0 => None,
+        index => Some(index as u8 - 1),
+    }
 }
 
-impl ParameterType {
-    pub const COUNT: usize = 3;
+fn cc_label(index: usize, _count: usize) -> String {
+    match cc_number(index) {
+        Some(cc) => format!("CC {}", cc),
+        None => "Off".to_string(),
+    }
 }
 
-impl_all! {RawParameters, ParameterType, table}
+/// Returns the number of repeats selected by option `index` of Repeats, or
+/// None for the last option, which repeats forever.
+fn repeat_count(index: usize) -> Option<usize> {
+    if index < MAX_REPEATS {
+        Some(index + 1)
+    } else {
+        None
+    }
+}
+
+fn repeats_label(index: usize, _count: usize) -> String {
+    match repeat_count(index) {
+        Some(repeats) => format!("{}", repeats),
+        None => "Infinite".to_string(),
+    }
+}
+
+/// Returns the division selected by `note` with Note Divisions on. `root`
+/// selects 1/4, and each whole step up halves the length, so that with a root
+/// of C, D selects 1/8 and E selects 1/16. The semitone between each pair of
+/// whole steps selects the triplet of the lower one, regardless of whether
+/// it is a black key (with a root of C, C# is 1/4T but F is 1/16T). Notes
+/// from 1/1 to 1/64 are mapped, and others return None.
+fn note_division(note: u8, root: u8) -> Option<Division> {
+    // 1/1 is two whole steps below the root.
+    let offset = note as i32 - root as i32 + 4;
+    if !(0..=12).contains(&offset) {
+        return None;
+    }
+    Some(Division {
+        denominator: 1 << (offset / 2),
+        feel: if offset % 2 == 0 {
+            Feel::Straight
+        } else {
+            Feel::Triplet
+        },
+    })
+}
+
+/// Returns the name of a MIDI note, where note 60 is C4.
+fn note_name(note: u8) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    format!("{}{}", NAMES[note as usize % 12], note as i32 / 12 - 1)
+}
+
+/// The raw parameter values that a host DAW will set and modify.
+/// These are unscaled and are always in the [0.0, 1.0] range
+#[derive(VstParameters)]
+#[parameters(parameter_type = ParameterType)]
+pub struct RawParameters {
+    #[parameter(name = "Wet/Dry", default = 1.0, range = Range::linear(0.0, 100.0, "%"))]
+    #[smoothed]
+    wet_dry: AtomicFloat,
+    #[parameter(name = "Trigger", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    trigger: AtomicFloat,
+    #[parameter(name = "Buffer Size", default = 0.5, range = Range::exp(0.0, 1.0, ""), strings = |x: usize, params: &Parameters| buffer_size_string(x, params))]
+    buffer_size: AtomicFloat,
+    #[parameter(name = "Trigger Note", default = 60.0 / 127.0, range = Range::discrete(0.0, 127.0, ""), strings = |x: u8, _| (note_name(x), "".to_string()))]
+    trigger_note: AtomicFloat,
+    #[parameter(name = "Trigger Channel", default = 0.0, range = Range::discrete(0.0, 16.0, ""), strings = |x: Option<u8>, _| match x {Some(x) => (format!("{}", x + 1), "".to_string()), None => ("Omni".to_string(), "".to_string())})]
+    trigger_channel: AtomicFloat,
+    #[parameter(name = "Sync", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    sync: AtomicFloat,
+    #[parameter(name = "Division", default = 0.3, range = Range::choice(Division::COUNT, choice_label::<Division>))]
+    division: AtomicFloat,
+    #[parameter(name = "Reverse", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    reverse: AtomicFloat,
+    #[parameter(name = "Speed", default = 0.5, range = Range::log(0.25, 4.0, "x"))]
+    speed: AtomicFloat,
+    #[parameter(name = "Pitch Step", default = 0.5, range = Range::discrete(-12.0, 12.0, "st"), strings = |x: f32, _| (format!("{:+}", x), "st".to_string()))]
+    pitch_step: AtomicFloat,
+    #[parameter(name = "Auto Rate", default = 0.6, range = Range::choice(Division::COUNT, choice_label::<Division>))]
+    auto_rate: AtomicFloat,
+    #[parameter(name = "Probability", default = 0.0, range = Range::linear(0.0, 100.0, "%"))]
+    probability: AtomicFloat,
+    #[parameter(name = "Quantize", default = 0.0, range = Range::choice(4, choice_label::<Quantize>))]
+    quantize: AtomicFloat,
+    #[parameter(name = "Ramp", default = 0.5, range = Range::linear(-100.0, 100.0, "%"))]
+    ramp: AtomicFloat,
+    #[parameter(name = "Ramp Time", default = 0.6, range = Range::log(10.0, 10000.0, "ms"))]
+    ramp_time: AtomicFloat,
+    #[parameter(name = "Slices", default = 0.0, range = Range::discrete(1.0, MAX_SLICES as f32, ""))]
+    slices: AtomicFloat,
+    #[parameter(name = "Slice", default = 0.0, range = Range::discrete(1.0, MAX_SLICES as f32, ""))]
+    slice: AtomicFloat,
+    #[parameter(name = "Repeats", default = 1.0, range = Range::choice(MAX_REPEATS + 1, repeats_label))]
+    repeats: AtomicFloat,
+    #[parameter(name = "Overdub", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    overdub: AtomicFloat,
+    #[parameter(name = "Feedback", default = 0.5, range = Range::linear(0.0, 100.0, "%"))]
+    feedback: AtomicFloat,
+    #[parameter(name = "Trigger CC", default = 0.0, range = Range::choice(CC_OPTIONS, cc_label))]
+    trigger_cc: AtomicFloat,
+    #[parameter(name = "Buffer Size CC", default = 0.0, range = Range::choice(CC_OPTIONS, cc_label))]
+    buffer_size_cc: AtomicFloat,
+    #[parameter(name = "Shuffle", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    shuffle: AtomicFloat,
+    #[parameter(name = "Gate Length", default = 1.0, range = Range::linear(0.0, 100.0, "%"))]
+    gate_length: AtomicFloat,
+    #[parameter(name = "Threshold", default = 0.0, range = Range::linear(-60.0, 0.0, "dB").with_off(0.0))]
+    threshold: AtomicFloat,
+    #[parameter(name = "Hold", default = 0.5, range = Range::log(10.0, 2000.0, "ms"))]
+    hold: AtomicFloat,
+    #[parameter(name = "Re-arm", default = 0.5, range = Range::log(10.0, 2000.0, "ms"))]
+    rearm: AtomicFloat,
+    #[parameter(name = "Pattern", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    pattern: AtomicFloat,
+    #[parameter(name = "Pattern Rate", default = 0.6, range = Range::choice(Division::COUNT, choice_label::<Division>))]
+    pattern_rate: AtomicFloat,
+    #[parameter(name = "Step 1", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_1: AtomicFloat,
+    #[parameter(name = "Step 2", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_2: AtomicFloat,
+    #[parameter(name = "Step 3", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_3: AtomicFloat,
+    #[parameter(name = "Step 4", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_4: AtomicFloat,
+    #[parameter(name = "Step 5", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_5: AtomicFloat,
+    #[parameter(name = "Step 6", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_6: AtomicFloat,
+    #[parameter(name = "Step 7", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_7: AtomicFloat,
+    #[parameter(name = "Step 8", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_8: AtomicFloat,
+    #[parameter(name = "Step 9", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_9: AtomicFloat,
+    #[parameter(name = "Step 10", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_10: AtomicFloat,
+    #[parameter(name = "Step 11", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_11: AtomicFloat,
+    #[parameter(name = "Step 12", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_12: AtomicFloat,
+    #[parameter(name = "Step 13", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_13: AtomicFloat,
+    #[parameter(name = "Step 14", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_14: AtomicFloat,
+    #[parameter(name = "Step 15", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_15: AtomicFloat,
+    #[parameter(name = "Step 16", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    step_16: AtomicFloat,
+    #[parameter(name = "Dry While Triggered", default = 0.0, range = Range::choice(3, choice_label::<DryMode>))]
+    dry_mode: AtomicFloat,
+    #[parameter(name = "Duck", default = 0.75, range = Range::linear(-48.0, 0.0, "dB"))]
+    duck: AtomicFloat,
+    #[parameter(name = "Release", default = 0.0, range = Range::choice(2, choice_label::<Release>))]
+    release: AtomicFloat,
+    #[parameter(name = "Tape Stop Time", default = 0.5, range = Range::log(50.0, 2000.0, "ms"))]
+    tape_stop_time: AtomicFloat,
+    #[parameter(name = "Pre-roll", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    preroll: AtomicFloat,
+    #[parameter(name = "Voices", default = 0.0, range = Range::discrete(1.0, MAX_VOICES as f32, ""))]
+    voices: AtomicFloat,
+    #[parameter(name = "Voice 2 Length", default = 2.5 / 9.0, range = Range::choice(VoiceLength::ALL.len(), choice_label::<VoiceLength>))]
+    voice_2_length: AtomicFloat,
+    #[parameter(name = "Voice 2 Level", default = 0.5, range = Range::linear(0.0, 100.0, "%"))]
+    voice_2_level: AtomicFloat,
+    #[parameter(name = "Voice 3 Length", default = 3.5 / 9.0, range = Range::choice(VoiceLength::ALL.len(), choice_label::<VoiceLength>))]
+    voice_3_length: AtomicFloat,
+    #[parameter(name = "Voice 3 Level", default = 0.5, range = Range::linear(0.0, 100.0, "%"))]
+    voice_3_level: AtomicFloat,
+    #[parameter(name = "Voice 4 Length", default = 4.5 / 9.0, range = Range::choice(VoiceLength::ALL.len(), choice_label::<VoiceLength>))]
+    voice_4_length: AtomicFloat,
+    #[parameter(name = "Voice 4 Level", default = 0.5, range = Range::linear(0.0, 100.0, "%"))]
+    voice_4_level: AtomicFloat,
+    #[parameter(name = "LFO Depth", default = 0.0, range = Range::linear(0.0, 100.0, "%"))]
+    lfo_depth: AtomicFloat,
+    #[parameter(name = "LFO Rate", default = 0.6, range = Range::choice(Division::COUNT, choice_label::<Division>))]
+    lfo_rate: AtomicFloat,
+    #[parameter(name = "LFO Shape", default = 0.0, range = Range::choice(6, choice_label::<LfoShape>))]
+    lfo_shape: AtomicFloat,
+    #[parameter(name = "Save Buffer", default = 0.0, range = Range::toggle("OFF", "Saving"))]
+    save_buffer: AtomicFloat,
+    #[parameter(name = "Key Track", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    key_track: AtomicFloat,
+    #[parameter(name = "Swing", default = 0.0, range = Range::linear(0.0, 75.0, "%"))]
+    swing: AtomicFloat,
+    #[parameter(name = "Right Length", default = 0.5, range = Range::log(0.5, 2.0, "x"))]
+    right_length: AtomicFloat,
+    #[parameter(name = "MIDI Out", default = 0.0, range = Range::choice(3, choice_label::<MidiOut>))]
+    midi_out: AtomicFloat,
+    #[parameter(name = "Output Note", default = 60.0 / 127.0, range = Range::discrete(0.0, 127.0, ""), strings = |x: u8, _| (note_name(x), "".to_string()))]
+    output_note: AtomicFloat,
+    #[parameter(name = "Ping-Pong", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    ping_pong: AtomicFloat,
+    #[parameter(name = "Offset", default = 0.0, range = Range::linear(0.0, 100.0, "%"))]
+    offset: AtomicFloat,
+    #[parameter(name = "Zero-Crossing Snap", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    snap: AtomicFloat,
+    #[parameter(name = "Note Divisions", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    note_divisions: AtomicFloat,
+    #[parameter(name = "Lookahead", default = 0.0, range = Range::toggle("OFF", "ON"))]
+    lookahead: AtomicFloat,
+    /// The index of the most recently selected built-in program
+    current_preset: AtomicI32,
+    /// Bumped whenever a parameter changes. See `generation`.
+    generation: AtomicUsize,
+    /// Changes made on the audio thread which the host hasn't been told about
+    pending: PendingEdits,
+    /// The sample rate most recently given by the host
+    pub sample_rate: AtomicFloat,
+    /// The tempo most recently reported by the host, in beats per
+    /// minute, or 0.0 if the host hasn't reported one
+    pub tempo: AtomicFloat,
+    /// The host callback, used for communicating with the VST host
+    pub host: HostCallback,
+}
+
+impl Automatable for ParameterType {
+    fn can_be_automated(&self) -> bool {
+        *self != ParameterType::SaveBuffer
+    }
+}
 
 // Export symbols for main
 vst::plugin_main!(Stutter);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn straight_steps_follow_position() {
+        for &(position, step) in &[(0.0, 0), (0.49, 0), (0.5, 1), (1.75, 3), (-0.25, -1)] {
+            assert_eq!(swung_step(position, 0.5, 0.0), step);
+        }
+    }
+
+    #[test]
+    fn swing_delays_every_other_step() {
+        // Sixteenths with 50% swing: the second of each pair starts half
+        // a sixteenth late.
+        let length = 0.25;
+        let swing = 0.5;
+        assert_eq!(swung_step(0.3, length, swing), 0);
+        assert_eq!(swung_step(0.375, length, swing), 1);
+        // The next pair starts on the beat, without any delay.
+        assert_eq!(swung_step(0.5, length, swing), 2);
+        assert_eq!(swung_step(0.8, length, swing), 2);
+        assert_eq!(swung_step(0.875, length, swing), 3);
+    }
+
+    /// The real value of `parameter` after typing `text` into the host.
+    fn parse(parameter: ParameterType, text: &str) -> Option<f32> {
+        let range = parameter.range();
+        range.parse(text).map(|x| range.to_real(x))
+    }
+
+    #[test]
+    fn slice_parses_as_typed() {
+        assert_eq!(parse(ParameterType::Slice, "3"), Some(3.0));
+        assert_eq!(parse(ParameterType::Slice, "99"), Some(MAX_SLICES as f32));
+    }
+
+    #[test]
+    fn repeats_parse_as_typed() {
+        let repeats = |text| parse(ParameterType::Repeats, text).map(|x| repeat_count(x as usize));
+        assert_eq!(repeats("4"), Some(Some(4)));
+        assert_eq!(repeats("1"), Some(Some(1)));
+        assert_eq!(repeats("Infinite"), Some(None));
+    }
+
+    #[test]
+    fn ccs_parse_as_typed() {
+        for &parameter in &[ParameterType::TriggerCc, ParameterType::BufferSizeCc] {
+            let cc = |text| parse(parameter, text).map(|x| cc_number(x as usize));
+            assert_eq!(cc("64"), Some(Some(64)));
+            assert_eq!(cc("CC 64"), Some(Some(64)));
+            assert_eq!(cc("0"), Some(Some(0)));
+            assert_eq!(cc("Off"), Some(None));
+        }
+    }
+}